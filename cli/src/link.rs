@@ -0,0 +1,119 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use common::{
+    command::{
+        commands::{HeadlightCommand, Request},
+        reader::{HeadlightCommandReader, LinkEvent, LinkObserver, ParseCommandBundle},
+        writer::HeadlightCommandWriter,
+    },
+    types::{Address, Protocol},
+    utils::bundles::FromHeadlightBundle,
+};
+use embedded_io_adapters::tokio_1::FromTokio;
+use tiny_serde::Serialize;
+use tokio::{
+    io::{split, BufReader, ReadHalf, WriteHalf},
+    time::timeout,
+};
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+/// Baud rate of the headlight's UART.
+pub const BAUD_RATE: u32 = 9600;
+
+/// How long a headlight has to answer a request.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+const BUF_SIZE: usize = 256;
+
+pub type Rx = FromTokio<BufReader<ReadHalf<SerialStream>>>;
+pub type Tx = FromTokio<WriteHalf<SerialStream>>;
+
+/// Reports link errors, which would otherwise go unnoticed on the host.
+pub struct Reporter(pub &'static str);
+
+impl LinkObserver for Reporter {
+    fn observe(&mut self, event: LinkEvent) {
+        let what = match event {
+            LinkEvent::Received => return,
+            LinkEvent::CrcFailure => "a command failed its CRC check",
+            LinkEvent::Malformed => "a command was malformed",
+            LinkEvent::Overflow => "incoming data overflowed the buffer",
+        };
+
+        eprintln!("[{}] {}", self.0, what);
+    }
+}
+
+pub type Reader = HeadlightCommandReader<Rx, BUF_SIZE, Reporter>;
+pub type Writer = HeadlightCommandWriter<Tx>;
+
+/// Open a serial port (or PTY) at `path`.
+pub fn open(path: &str, baud: u32) -> Result<(Rx, Tx)> {
+    let stream = tokio_serial::new(path, baud)
+        .open_native_async()
+        .map_err(|e| anyhow!("failed to open {}: {}", path, e))?;
+    let (rx, tx) = split(stream);
+
+    Ok((FromTokio::new(BufReader::new(rx)), FromTokio::new(tx)))
+}
+
+/// A link to a headlight, standing in for the relay.
+pub struct Link {
+    reader: Reader,
+    writer: Writer,
+}
+
+impl Link {
+    /// Open a link to the headlight at `address` (on a bus, or point-to-point).
+    pub fn open(path: &str, baud: u32, address: Address) -> Result<Self> {
+        let (rx, tx) = open(path, baud)?;
+        let protocol = Protocol::relay(address);
+
+        Ok(Self {
+            reader: HeadlightCommandReader::with_observer(rx, Reporter("link"))
+                .with_protocol(protocol),
+            writer: HeadlightCommandWriter::with_protocol(tx, protocol),
+        })
+    }
+
+    pub async fn send<C, const N: usize>(&mut self, cmd: C) -> Result<()>
+    where
+        C: HeadlightCommand + Serialize<N>,
+    {
+        Ok(self.writer.send(cmd).await?)
+    }
+
+    /// Wait for the first command `pick` accepts, ignoring the rest.
+    pub async fn wait_for<Bundle, T>(
+        &mut self,
+        mut pick: impl FnMut(Bundle) -> Option<T>,
+    ) -> Result<T>
+    where
+        Bundle: ParseCommandBundle,
+    {
+        let wait = async {
+            loop {
+                let (_, bundle) = self.reader.receive().await?;
+
+                if let Some(picked) = pick(bundle) {
+                    return anyhow::Ok(picked);
+                }
+            }
+        };
+
+        timeout(RESPONSE_TIMEOUT, wait)
+            .await
+            .map_err(|_| anyhow!("the headlight did not respond"))?
+    }
+
+    /// Send `request` and wait for the response `pick` accepts.
+    pub async fn request<T>(
+        &mut self,
+        request: Request,
+        pick: impl FnMut(FromHeadlightBundle) -> Option<T>,
+    ) -> Result<T> {
+        self.send(request).await?;
+        self.wait_for(pick).await
+    }
+}
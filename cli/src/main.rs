@@ -0,0 +1,211 @@
+mod link;
+mod sniff;
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use common::{
+    command::commands::{Config, Control, Request, Reset},
+    types::{Address, POINT_TO_POINT},
+    utils::{bundles::FromHeadlightBundle, validation::validate_config},
+};
+use tokio::time::sleep;
+
+use crate::link::{Link, BAUD_RATE};
+
+/// Interval between monitor requests while following.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Parser)]
+#[command(about = "Talk to a headlight over a serial port, in place of the relay")]
+struct Cli {
+    /// Serial port (or PTY) connected to the headlight's UART
+    #[arg(short, long)]
+    port: String,
+
+    #[arg(short, long, default_value_t = BAUD_RATE)]
+    baud: u32,
+
+    /// The headlight's address on a shared bus (0 for a point-to-point link)
+    #[arg(short, long, default_value_t = POINT_TO_POINT)]
+    address: Address,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the headlight's status (mode and faults)
+    Status,
+    /// Print the regulator's measurements
+    Monitor {
+        /// Keep printing until interrupted
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Set the target current
+    Control {
+        /// Target in mA
+        target: u16,
+    },
+    /// Read or write the headlight's config
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Reset the headlight
+    Reset {
+        /// Restore the default config first
+        #[arg(long)]
+        factory: bool,
+    },
+    /// Decode every command on the link without sending anything
+    Sniff {
+        /// Port tapping the relay's TX, to decode commands to the headlight as well
+        #[arg(long)]
+        to_headlight: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the config
+    Get {
+        #[arg(short, long, value_enum, default_value_t = Format::Toml)]
+        format: Format,
+    },
+    /// Validate a config file against the headlight's properties and write it
+    Set {
+        /// TOML or JSON file, by extension unless `--format` is given
+        file: PathBuf,
+
+        #[arg(short, long, value_enum)]
+        format: Option<Format>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    fn of(file: &Path) -> Self {
+        match file.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Self::Json,
+            _ => Self::Toml,
+        }
+    }
+
+    fn print(self, config: &Config) -> Result<String> {
+        Ok(match self {
+            Self::Toml => toml::to_string_pretty(config)?,
+            Self::Json => serde_json::to_string_pretty(config)?,
+        })
+    }
+
+    fn parse(self, text: &str) -> Result<Config> {
+        Ok(match self {
+            Self::Toml => toml::from_str(text)?,
+            Self::Json => serde_json::from_str(text)?,
+        })
+    }
+}
+
+async fn monitor(link: &mut Link, follow: bool) -> Result<()> {
+    loop {
+        let monitor = link
+            .request(Request::Monitor, |bundle| match bundle {
+                FromHeadlightBundle::Monitor(monitor) => Some(monitor),
+                _ => None,
+            })
+            .await
+            .map_err(|e| anyhow!("{} (monitoring is only available while regulating)", e))?;
+
+        println!("{:?}", monitor);
+
+        if !follow {
+            return Ok(());
+        }
+
+        sleep(FOLLOW_INTERVAL).await;
+    }
+}
+
+async fn config(link: &mut Link, action: ConfigAction) -> Result<()> {
+    match action {
+        ConfigAction::Get { format } => {
+            let config = link
+                .request(Request::Config, |bundle| match bundle {
+                    FromHeadlightBundle::Config(config) => Some(config),
+                    _ => None,
+                })
+                .await?;
+
+            print!("{}", format.print(&config)?);
+        }
+        ConfigAction::Set { file, format } => {
+            let text = std::fs::read_to_string(&file)?;
+            let config = format.unwrap_or_else(|| Format::of(&file)).parse(&text)?;
+
+            let properties = link
+                .request(Request::Properties, |bundle| match bundle {
+                    FromHeadlightBundle::Properties(properties) => Some(properties),
+                    _ => None,
+                })
+                .await?;
+
+            let errors = validate_config(&config, &properties);
+
+            if !errors.is_empty() {
+                bail!("config is invalid: {:?}", errors);
+            }
+
+            link.send(config).await?;
+            println!("Config written, the headlight resets to apply it.");
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    if let Command::Sniff { to_headlight } = &cli.command {
+        return sniff::sniff(&cli.port, to_headlight.as_deref(), cli.baud).await;
+    }
+
+    let mut link = Link::open(&cli.port, cli.baud, cli.address)?;
+
+    match cli.command {
+        Command::Status => {
+            let status = link
+                .request(Request::Status, |bundle| match bundle {
+                    FromHeadlightBundle::Status(status) => Some(status),
+                    _ => None,
+                })
+                .await?;
+
+            println!("{:?}", status);
+        }
+        Command::Monitor { follow } => monitor(&mut link, follow).await?,
+        Command::Control { target } => link.send(Control { target }).await?,
+        Command::Config { action } => config(&mut link, action).await?,
+        Command::Reset { factory } => {
+            link.send(if factory { Reset::Factory } else { Reset::Now })
+                .await?
+        }
+        // sniffing never sends, so it was handled before opening the link
+        Command::Sniff { .. } => unreachable!(),
+    }
+
+    Ok(())
+}
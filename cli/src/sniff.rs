@@ -0,0 +1,63 @@
+use std::{fmt::Display, time::Instant};
+
+use anyhow::Result;
+use common::{
+    command::reader::{HeadlightCommandReader, ParseCommandBundle},
+    std_serde_impls::describe_std_log,
+    use_from_headlight_bundle, use_to_headlight_bundle,
+    utils::bundles::{FromHeadlightBundle, ToHeadlightBundle},
+};
+use tokio::try_join;
+
+use crate::link::{open, Reader, Reporter};
+
+fn print(start: Instant, direction: &str, cmd: impl Display) {
+    println!(
+        "{:>10.3} {} {}",
+        start.elapsed().as_secs_f32(),
+        direction,
+        cmd
+    );
+}
+
+/// Print every command received on `reader` until the port fails.
+async fn tap<Bundle>(mut reader: Reader, mut describe: impl FnMut(Bundle)) -> Result<()>
+where
+    Bundle: ParseCommandBundle,
+{
+    loop {
+        let (_, bundle) = reader.receive().await?;
+        describe(bundle);
+    }
+}
+
+/// Decode commands from the headlight on `from_headlight`, and optionally commands
+/// to the headlight on `to_headlight` (both ports tapping the RX side of the link).
+pub async fn sniff(from_headlight: &str, to_headlight: Option<&str>, baud: u32) -> Result<()> {
+    let start = Instant::now();
+
+    let (rx, _) = open(from_headlight, baud)?;
+    let upstream = tap(
+        HeadlightCommandReader::with_observer(rx, Reporter("headlight")),
+        |bundle: FromHeadlightBundle| match bundle {
+            FromHeadlightBundle::Log(entry) => print(start, "<-", describe_std_log(&entry)),
+            bundle => use_from_headlight_bundle!(bundle, |cmd| {
+                print(start, "<-", format!("{:?}", cmd))
+            }),
+        },
+    );
+
+    let Some(to_headlight) = to_headlight else {
+        return upstream.await;
+    };
+
+    let (rx, _) = open(to_headlight, baud)?;
+    let downstream = tap(
+        HeadlightCommandReader::with_observer(rx, Reporter("relay")),
+        |bundle: ToHeadlightBundle| {
+            use_to_headlight_bundle!(bundle, |cmd| print(start, "->", format!("{:?}", cmd)))
+        },
+    );
+
+    try_join!(upstream, downstream).map(|_| ())
+}
@@ -4,7 +4,11 @@ use tiny_serde_macros::{Deserialize, Serialize};
 #[cfg(feature = "defmt")]
 use defmt::Format;
 
-use crate::types::{CommandID, HeadlightError, Mode, Version};
+use crate::types::{
+    Address, AmbientLight, CommandID, DeratingReason, DfuStage, Faults, FirmwareError, Gesture,
+    HeadlightError, InputAction, LogLevel, LogModule, LogSource, Mode, RuleAction, RuleTrigger,
+    Side, Thermistor, VehicleSignal, Version, POINT_TO_POINT,
+};
 
 pub trait HeadlightCommand {
     const ID: CommandID;
@@ -13,6 +17,7 @@ pub trait HeadlightCommand {
 // diagnostic -- should not occur in prod
 #[derive(Clone, Copy, Serialize, Deserialize)]
 #[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
 #[repr(u8)]
 pub enum AppError {
     None = 0x00,
@@ -22,16 +27,35 @@ pub enum AppError {
     SendFault = 0x11,
     /// app sent a command before the previous finished dispatching
     TooFast = 0x12,
+    /// another app holds the control lease
+    ControlLeased = 0x13,
+}
+
+/// Sent periodically by the relay and echoed by the headlight to detect a lost link.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct Heartbeat {
+    pub seq: u32,
+}
+
+impl HeadlightCommand for Heartbeat {
+    const ID: CommandID = 0x1e;
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 #[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
 #[repr(u8)]
 pub enum Request {
     Status = Status::ID,
     Control = Control::ID,
     Monitor = Monitor::ID,
     Config = Config::ID,
+    Properties = Properties::ID,
+    Derating = Derating::ID,
+    /// Every buffered [`Log`] entry, oldest first
+    Log = Log::ID,
 }
 
 impl HeadlightCommand for Request {
@@ -40,18 +64,74 @@ impl HeadlightCommand for Request {
 
 #[derive(Clone, Copy, Default, Serialize, Deserialize)]
 #[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
 pub struct Status {
     pub mode: Mode,
-    pub error: HeadlightError,
+    /// Every warning and fault raised since they were last cleared
+    pub faults: Faults,
+    /// The first fault raised since faults were last cleared
+    pub first_fault: HeadlightError,
+    /// The most recently raised fault
+    pub latest_fault: HeadlightError,
+    /// When the most recent fault was raised (Unix time in seconds), 0 if the time was not known
+    pub latest_fault_time: u32,
+}
+
+impl Status {
+    pub fn raise(&mut self, error: HeadlightError) {
+        if let HeadlightError::None = error {
+            return;
+        }
+
+        if let HeadlightError::None = self.first_fault {
+            self.first_fault = error;
+        }
+
+        self.latest_fault = error;
+        self.faults.insert(error);
+    }
+
+    /// Removes faults from the active set.
+    ///
+    /// The first and latest faults are only forgotten once no faults remain.
+    pub fn clear(&mut self, faults: Faults) {
+        self.faults.remove(faults);
+
+        if self.faults.is_empty() {
+            self.first_fault = HeadlightError::None;
+            self.latest_fault = HeadlightError::None;
+            self.latest_fault_time = 0;
+        }
+    }
 }
 
 impl HeadlightCommand for Status {
     const ID: CommandID = 0x1f;
 }
 
+/// Wall-clock time written by the app, and forwarded by the relay to the headlights.
+///
+/// Devices only know their uptime, so this must be sent again after they reset.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct TimeSync {
+    /// Seconds since the Unix epoch (UTC)
+    pub unix: u32,
+    /// Milliseconds into the second
+    pub millis: u16,
+}
+
+impl HeadlightCommand for TimeSync {
+    const ID: CommandID = 0x1d;
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
 #[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Control {
     pub target: u16,
 }
@@ -63,6 +143,7 @@ impl HeadlightCommand for Control {
 #[derive(Clone, Serialize, Deserialize)]
 #[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
 #[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
 pub struct Monitor {
     /// Duty cycle of regulation PWM
     pub duty: u16,
@@ -78,9 +159,28 @@ impl HeadlightCommand for Monitor {
     const ID: CommandID = 0xab;
 }
 
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct Derating {
+    /// Target requested by the active control scheme
+    pub requested_target: u16,
+    /// Target actually being regulated to
+    pub effective_target: u16,
+    /// Why the effective target is below the requested target
+    pub reason: DeratingReason,
+}
+
+impl HeadlightCommand for Derating {
+    const ID: CommandID = 0xad;
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
 #[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Config {
     /// Indicates whether or not to begin regulation
     pub enabled: bool,
@@ -98,6 +198,14 @@ pub struct Config {
     pub throttle_start: u8,
     /// Temperature to stop throttling at (overheating)
     pub throttle_stop: u8,
+    /// Behavior of the button and high/low switch
+    pub input: InputConfig,
+    /// Behavior in response to the vehicle's signals
+    pub signals: SignalConfig,
+    /// Automatic brightness following the ambient light sensor
+    pub ambient: AmbientConfig,
+    /// Address on a shared bus, or [`POINT_TO_POINT`] for a link of its own
+    pub address: Address,
 }
 
 impl Default for Config {
@@ -111,6 +219,10 @@ impl Default for Config {
             abs_max_load_current: 100,
             throttle_start: 50,
             throttle_stop: 60,
+            input: InputConfig::default(),
+            signals: SignalConfig::default(),
+            ambient: AmbientConfig::default(),
+            address: POINT_TO_POINT,
         }
     }
 }
@@ -119,24 +231,406 @@ impl HeadlightCommand for Config {
     const ID: CommandID = 0xac;
 }
 
+/// Targets stepped through by [`InputAction::CyclePreset`], in order.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Presets {
+    pub low: u16,
+    pub medium: u16,
+    pub high: u16,
+}
+
+impl Presets {
+    pub const fn targets(&self) -> [u16; 3] {
+        [self.low, self.medium, self.high]
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InputConfig {
+    pub short_press: InputAction,
+    pub long_press: InputAction,
+    pub double_press: InputAction,
+    pub presets: Presets,
+    /// Target while the high/low switch is high, or while flashing to pass
+    pub high_beam: u16,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            short_press: InputAction::Toggle,
+            long_press: InputAction::FlashToPass,
+            double_press: InputAction::CyclePreset,
+            presets: Presets {
+                low: 10,
+                medium: 25,
+                high: 40,
+            },
+            high_beam: 50,
+        }
+    }
+}
+
+/// Reported when a physical input changes the headlight's behavior,
+/// the resulting control is reported separately.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct InputEvent {
+    pub gesture: Gesture,
+    /// The switch has a fixed behavior, so its action is always [`InputAction::None`]
+    pub action: InputAction,
+}
+
+impl HeadlightCommand for InputEvent {
+    const ID: CommandID = 0xae;
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SignalConfig {
+    /// Side of the vehicle the headlight is mounted on, it only reacts to that side's turn signal
+    ///
+    /// The relay sets this from the link the headlight is wired to, whatever was written.
+    pub side: Side,
+    /// Percentage of the target kept while turning towards this side (100 to disable)
+    pub turn_dim: u8,
+    /// Current added to the target while braking (0 to disable)
+    pub brake_boost: u16,
+    /// Enter standby when the ignition turns off (turning it on always wakes the headlight)
+    pub ignition_standby: bool,
+}
+
+impl Default for SignalConfig {
+    fn default() -> Self {
+        Self {
+            side: Side::Left,
+            turn_dim: 30,
+            brake_boost: 0,
+            ignition_standby: false,
+        }
+    }
+}
+
+/// Reported when one of the vehicle's signals turns on or off.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct SignalEvent {
+    pub signal: VehicleSignal,
+    pub active: bool,
+}
+
+impl HeadlightCommand for SignalEvent {
+    const ID: CommandID = 0xaf;
+}
+
+/// Light levels are raw samples of the light sensor's divider (0 to 4095), brighter is higher.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AmbientConfig {
+    /// Fade between the day and night targets by itself instead of waiting for a control
+    pub auto: bool,
+    /// Target during the day (daytime running light)
+    pub day_target: u16,
+    /// Target from dusk until it is day again
+    pub night_target: u16,
+    /// Light level at or below which it is night
+    pub dusk_level: u16,
+    /// Light level at or above which it is day again, must be above `dusk_level`
+    pub dawn_level: u16,
+    /// Time taken to fade between the targets (ms)
+    pub transition: u16,
+}
+
+impl Default for AmbientConfig {
+    fn default() -> Self {
+        Self {
+            auto: false,
+            day_target: 10,
+            night_target: 40,
+            dusk_level: 800,
+            dawn_level: 1200,
+            transition: 3000,
+        }
+    }
+}
+
+/// Reported when the ambient light changes between day and night,
+/// the resulting control is reported separately.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct AmbientEvent {
+    pub light: AmbientLight,
+    /// The light level that caused the change
+    pub level: u16,
+}
+
+impl HeadlightCommand for AmbientEvent {
+    const ID: CommandID = 0xb0;
+}
+
+/// A diagnostic log entry, kept in a ring buffer on each device.
+///
+/// Headlights forward every entry to the relay, which notifies them to the app with its own.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct Log {
+    /// Unix time in seconds, 0 if the time was not known
+    pub time: u32,
+    /// Milliseconds since the device booted
+    pub uptime: u32,
+    pub source: LogSource,
+    pub level: LogLevel,
+    pub module: LogModule,
+    /// What happened, specific to the module
+    pub code: u8,
+    /// Meaning depends on the code
+    pub arg0: u32,
+    pub arg1: u32,
+}
+
+impl HeadlightCommand for Log {
+    const ID: CommandID = 0xb1;
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
 #[repr(u8)]
 pub enum Reset {
     Now = 0x10,
     Factory = 0x11,
+    /// Enter standby, waking resets the headlight
+    Standby = 0x12,
 }
 
 impl HeadlightCommand for Reset {
     const ID: CommandID = 0xff;
 }
 
-// this need not conform to HeadlightCommand
-// as it is not exchanged between the devices
-// (reflected by the fact it is not present
-// in either command bundle)
-#[derive(Serialize, Deserialize)]
+/// Acknowledges faults, removing them from the active set.
+///
+/// Clearing a fault does not resume regulation, a reset is required for that.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct ClearFault {
+    pub faults: Faults,
+}
+
+impl HeadlightCommand for ClearFault {
+    const ID: CommandID = 0xfe;
+}
+
+/// Number of image bytes carried by each [`FirmwareChunk`].
+pub const FIRMWARE_CHUNK_SIZE: usize = 32;
+
+/// Begins a firmware update.
+///
+/// Regulation is shut down and stays down until the headlight resets.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct FirmwareBegin {
+    /// Size of the image in bytes
+    pub size: u32,
+    /// CRC of the whole image
+    pub crc: u32,
+}
+
+impl HeadlightCommand for FirmwareBegin {
+    const ID: CommandID = 0xf0;
+}
+
+/// A piece of the image being transferred.
+///
+/// Chunks must be sent in order, the final chunk is padded with `0xff`.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct FirmwareChunk {
+    pub offset: u32,
+    pub data: [u8; FIRMWARE_CHUNK_SIZE],
+}
+
+impl HeadlightCommand for FirmwareChunk {
+    const ID: CommandID = 0xf1;
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[repr(u8)]
+pub enum FirmwareCommit {
+    /// Verify the image and reset into it
+    Apply = 0x10,
+    /// Discard the transfer
+    Abort = 0x11,
+}
+
+impl HeadlightCommand for FirmwareCommit {
+    const ID: CommandID = 0xf2;
+}
+
+/// Acknowledgement of every firmware update command.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
 #[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct FirmwareStatus {
+    /// Number of image bytes received so far
+    pub received: u32,
+    pub error: FirmwareError,
+}
+
+impl HeadlightCommand for FirmwareStatus {
+    const ID: CommandID = 0xf3;
+}
+
+// over-the-air update -- exchanged between the app and the relay only
+
+/// Number of image bytes carried by each [`DfuChunk`].
+pub const DFU_CHUNK_SIZE: usize = 128;
+
+/// A piece of the image being transferred to the relay.
+///
+/// The app must wait for the [`DfuProgress`] acknowledging each chunk before sending the next.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct DfuChunk {
+    pub offset: u32,
+    pub data: [u8; DFU_CHUNK_SIZE],
+}
+
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct DfuProgress {
+    pub stage: DfuStage,
+    /// Number of image bytes transferred in the current stage
+    pub transferred: u32,
+    /// Size of the image in bytes
+    pub size: u32,
+    pub error: FirmwareError,
+}
+
+// advertising -- broadcast by the relay
+
+/// Company identifier preceding the [`Beacon`] in manufacturer specific advertising data
+/// (reserved for testing until one is assigned).
+pub const BEACON_COMPANY_ID: u16 = 0xffff;
+
+/// Headlight health broadcast by the relay, readable without connecting.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct Beacon {
+    pub mode: Mode,
+    /// The most recently raised fault
+    pub latest_fault: HeadlightError,
+    /// FET temperature in degrees Celsius
+    pub temperature: u8,
+    /// Target after derating
+    pub target: u16,
+}
+
+// link health -- exchanged between the app and the relay only
+
+/// Health of the UART link between the relay and the headlight.
+#[derive(Clone, Copy, Default, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct LinkStatus {
+    /// Whether the headlight answered the latest heartbeat
+    pub alive: bool,
+    /// Number of valid commands received
+    pub received: u32,
+    pub crc_failures: u32,
+    pub malformed: u32,
+    pub overflows: u32,
+}
+
+// schedule -- exchanged between the app and the relay only
+
+/// Number of rules the relay holds.
+pub const MAX_RULES: usize = 8;
+
+/// A timed rule the relay executes by itself, even with no app connected.
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct Rule {
+    pub trigger: RuleTrigger,
+    /// Time from the trigger until the action (s)
+    pub delay: u16,
+    pub action: RuleAction,
+    /// Target for [`RuleAction::SetTarget`]
+    pub target: u16,
+}
+
+/// Every rule slot, empty slots are [`RuleTrigger::Disabled`].
+pub type Schedule = [Rule; MAX_RULES];
+
+pub const SCHEDULE_SIZE: usize = MAX_RULES * <Rule as _TinyDeSized>::SIZE;
+
+/// Rules are serialized back to back.
+pub fn serialize_schedule(schedule: &Schedule) -> [u8; SCHEDULE_SIZE] {
+    let mut data = [0; SCHEDULE_SIZE];
+
+    for (chunk, rule) in data
+        .chunks_exact_mut(<Rule as _TinyDeSized>::SIZE)
+        .zip(schedule)
+    {
+        chunk.copy_from_slice(&rule.serialize());
+    }
+
+    data
+}
+
+pub fn deserialize_schedule(data: [u8; SCHEDULE_SIZE]) -> Option<Schedule> {
+    let mut schedule = Schedule::default();
+
+    for (rule, chunk) in schedule
+        .iter_mut()
+        .zip(data.chunks_exact(<Rule as _TinyDeSized>::SIZE))
+    {
+        *rule = Rule::deserialize(chunk.try_into().ok()?)?;
+    }
+
+    Some(schedule)
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
 pub struct Properties {
     /// device version
     pub version: Version,
@@ -153,4 +647,15 @@ pub struct Properties {
 
     /// max mA measurement (adc) error
     pub max_adc_error: u16,
+
+    /// current sense scaling (mA = mV * sense_gain / r_shunt)
+    pub sense_gain: u16,
+    /// current sense shunt resistance (deci-ohms)
+    pub r_shunt: u16,
+    /// thermistor used for FET temperature
+    pub thermistor: Thermistor,
+}
+
+impl HeadlightCommand for Properties {
+    const ID: CommandID = 0xa0;
 }
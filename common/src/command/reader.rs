@@ -2,11 +2,13 @@ use core::future::Future;
 
 use crate::{
     fmt::warn,
-    types::{CRCRepr, CommandHeader, CommandID},
+    types::{Address, CRCRepr, CommandHeader, CommandID, Protocol, Route, V2_MARKER},
     utils::scan_buf::ScanBuf,
     CRC,
 };
 use crc::Digest;
+#[cfg(feature = "defmt")]
+use defmt::Format;
 use embedded_io_async::{BufRead, ErrorType};
 use pattern::{Pattern, PatternError};
 
@@ -21,14 +23,39 @@ where
     ) -> Result<Option<Self>, PatternError>;
 }
 
+/// Events observed by the reader, used to monitor link quality.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(Format))]
+pub enum LinkEvent {
+    /// a valid command was received
+    Received,
+    /// a command failed its CRC check
+    CrcFailure,
+    /// a command could not be deserialized
+    Malformed,
+    /// incoming data did not fit in the buffer and was discarded
+    Overflow,
+}
+
+pub trait LinkObserver {
+    fn observe(&mut self, event: LinkEvent);
+}
+
+/// Link events are ignored by default.
+impl LinkObserver for () {
+    fn observe(&mut self, _event: LinkEvent) {}
+}
+
 struct ParsedCommand<Bundle>(usize, CommandHeader, CRCRepr, Bundle);
 
-pub struct HeadlightCommandReader<HWReader, const N: usize>
+pub struct HeadlightCommandReader<HWReader, const N: usize, Observer = ()>
 where
     HWReader: BufRead,
 {
     rx: HWReader,
     buf: ScanBuf<N>,
+    observer: Observer,
+    protocol: Protocol,
 }
 
 impl<HWReader, const N: usize> HeadlightCommandReader<HWReader, N>
@@ -36,12 +63,29 @@ where
     HWReader: BufRead,
 {
     pub const fn new(rx: HWReader) -> Self {
+        Self::with_observer(rx, ())
+    }
+}
+
+impl<HWReader, const N: usize, Observer> HeadlightCommandReader<HWReader, N, Observer>
+where
+    HWReader: BufRead,
+    Observer: LinkObserver,
+{
+    pub const fn with_observer(rx: HWReader, observer: Observer) -> Self {
         Self {
             rx,
             buf: ScanBuf::new(),
+            observer,
+            protocol: Protocol::V1,
         }
     }
 
+    /// Frame commands with `protocol` rather than the default point-to-point [`Protocol::V1`].
+    pub fn with_protocol(self, protocol: Protocol) -> Self {
+        Self { protocol, ..self }
+    }
+
     async fn poll(&mut self) -> Result<(), <HWReader as ErrorType>::Error> {
         let incoming = self.rx.fill_buf().await?;
         let n = incoming.len();
@@ -49,6 +93,7 @@ where
         if self.buf.push_slice(incoming).is_err() {
             // error!("Incoming UART buffer full, data is being lost.");
             self.buf.clear();
+            self.observer.observe(LinkEvent::Overflow);
         }
 
         self.rx.consume(n);
@@ -64,15 +109,28 @@ where
 
         loop {
             let mut digest = CRC.digest();
-            let [id] = pattern.get().extract_and(|bytes| digest.update(bytes))?;
+            let [first]: [CommandID; 1] =
+                pattern.get().extract_and(|bytes| digest.update(bytes))?;
 
             let mut lookahead = pattern.clone();
+
+            // frames of either version are recognized, so peers of either version interoperate
+            let (id, route) = if first == V2_MARKER {
+                let [id] = lookahead.get().extract_and(|bytes| digest.update(bytes))?;
+                let [route]: [Route; 1] =
+                    lookahead.get().extract_and(|bytes| digest.update(bytes))?;
+
+                (id, Some(route))
+            } else {
+                (first, None)
+            };
+
             let [crc] = lookahead.get().extract()?;
 
             if let Some(bundle) = Bundle::parse(id, &mut lookahead, &mut digest)? {
                 break Ok(ParsedCommand(
                     lookahead.count(),
-                    CommandHeader { id, crc },
+                    CommandHeader { id, route, crc },
                     digest.finalize(),
                     bundle,
                 ));
@@ -98,7 +156,18 @@ where
         }
     }
 
-    fn recognizes<Bundle>(&mut self) -> Option<Bundle>
+    /// Whether a command with `route` is meant for this device.
+    ///
+    /// Unmarked commands only come from a [`Protocol::V1`] peer, which is alone on its link,
+    /// and a V1 device is alone on its link too, so both are always meant for this device.
+    fn accepts(&self, route: Option<Route>) -> bool {
+        match (self.protocol, route) {
+            (Protocol::V2 { local, .. }, Some(route)) => route.reaches(local),
+            _ => true,
+        }
+    }
+
+    fn recognizes<Bundle>(&mut self) -> Option<(Option<Address>, Bundle)>
     where
         Bundle: ParseCommandBundle,
     {
@@ -106,12 +175,28 @@ where
             Ok(ParsedCommand(count, header, observed_crc, bundle)) => {
                 self.buf.eat(count);
 
-                Self::validate_crc(header, observed_crc, bundle)
+                let route = header.route;
+
+                match Self::validate_crc(header, observed_crc, bundle) {
+                    Some(bundle) if self.accepts(route) => {
+                        self.observer.observe(LinkEvent::Received);
+
+                        Some((route.map(|route| route.src), bundle))
+                    }
+                    // commands for other devices on the bus are not this link's concern
+                    Some(_) => None,
+                    None => {
+                        self.observer.observe(LinkEvent::CrcFailure);
+
+                        None
+                    }
+                }
             }
             Err(PatternError::FailedDeserialize(count)) => {
                 self.buf.eat(count);
 
                 warn!("Received command was malformed, ignoring...");
+                self.observer.observe(LinkEvent::Malformed);
 
                 None
             }
@@ -124,16 +209,145 @@ where
         F: FnMut(Bundle) -> Fut,
         Fut: Future<Output = ()>,
         Bundle: ParseCommandBundle,
+    {
+        self.dispatch_from(|_, bundle| f(bundle)).await
+    }
+
+    /// Like [`dispatch`](Self::dispatch), but also provides the address of the sender
+    /// (which is only known with [`Protocol::V2`]).
+    pub async fn dispatch_from<F, Fut, Bundle>(&mut self, mut f: F)
+    where
+        F: FnMut(Option<Address>, Bundle) -> Fut,
+        Fut: Future<Output = ()>,
+        Bundle: ParseCommandBundle,
     {
         loop {
-            if let Err(_) = self.poll().await {
+            let Ok((source, bundle)) = self.receive().await else {
                 // error!("Command reader failed to poll with error: {}", e);
                 return;
+            };
+
+            f(source, bundle).await
+        }
+    }
+
+    /// Wait for the next command and the address of its sender,
+    /// for callers that handle one command at a time rather than [`dispatch`](Self::dispatch)ing.
+    pub async fn receive<Bundle>(
+        &mut self,
+    ) -> Result<(Option<Address>, Bundle), <HWReader as ErrorType>::Error>
+    where
+        Bundle: ParseCommandBundle,
+    {
+        loop {
+            // a single poll may have buffered several commands
+            if let Some(received) = self.recognizes() {
+                return Ok(received);
             }
 
-            if let Some(bundle) = self.recognizes() {
-                f(bundle).await
+            self.poll().await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+    use crate::{
+        command::{commands::Heartbeat, writer::HeadlightCommandWriter},
+        types::{BROADCAST, POINT_TO_POINT, RELAY},
+        utils::bundles::{FromHeadlightBundle, ToHeadlightBundle},
+    };
+
+    const HEADLIGHT: Address = 0x10;
+
+    /// Write `seq` with `protocol`, returning the bytes on the wire.
+    fn send(protocol: Protocol, dst: Option<Address>, seq: u32) -> Vec<u8> {
+        let mut wire = [0; 32];
+        let mut tx = &mut wire[..];
+        let mut writer = HeadlightCommandWriter::with_protocol(&mut tx, protocol);
+
+        block_on(async {
+            match dst {
+                Some(dst) => writer.send_to(dst, Heartbeat { seq }).await,
+                None => writer.send(Heartbeat { seq }).await,
             }
+        })
+        .unwrap();
+
+        let len = 32 - tx.len();
+        wire[..len].to_vec()
+    }
+
+    /// Read every command on `wire` with `protocol`, returning the sender and sequence number of each.
+    fn receive<Bundle>(
+        protocol: Protocol,
+        wire: &[u8],
+        seq: impl Fn(Bundle) -> u32,
+    ) -> Vec<(Option<Address>, u32)>
+    where
+        Bundle: ParseCommandBundle,
+    {
+        let mut reader = HeadlightCommandReader::<_, 64>::new(wire).with_protocol(protocol);
+        let mut received = Vec::new();
+
+        // a slice is read whole in one poll
+        block_on(reader.poll()).unwrap();
+
+        while let Some((source, bundle)) = reader.recognizes::<Bundle>() {
+            received.push((source, seq(bundle)));
         }
+
+        received
+    }
+
+    fn to_headlight(bundle: ToHeadlightBundle) -> u32 {
+        match bundle {
+            ToHeadlightBundle::Heartbeat(heartbeat) => heartbeat.seq,
+            _ => panic!("unexpected command"),
+        }
+    }
+
+    fn from_headlight(bundle: FromHeadlightBundle) -> u32 {
+        match bundle {
+            FromHeadlightBundle::Heartbeat(heartbeat) => heartbeat.seq,
+            _ => panic!("unexpected command"),
+        }
+    }
+
+    #[test]
+    fn v1_relay_reaches_v2_headlight() {
+        let wire = send(Protocol::relay(POINT_TO_POINT), None, 1);
+
+        assert_eq!(
+            receive(Protocol::headlight(HEADLIGHT), &wire, to_headlight),
+            [(None, 1)]
+        );
+    }
+
+    #[test]
+    fn v2_headlight_reaches_v1_relay() {
+        let wire = send(Protocol::headlight(HEADLIGHT), None, 2);
+
+        assert_eq!(
+            receive(Protocol::V1, &wire, from_headlight),
+            [(Some(HEADLIGHT), 2)]
+        );
+    }
+
+    #[test]
+    fn v2_peers_filter_by_address() {
+        let relay = Protocol::relay(HEADLIGHT);
+
+        let mut wire = send(relay, Some(HEADLIGHT + 1), 3);
+        wire.extend(send(relay, None, 4));
+        wire.extend(send(relay, Some(BROADCAST), 5));
+
+        assert_eq!(
+            receive(Protocol::headlight(HEADLIGHT), &wire, to_headlight),
+            [(Some(RELAY), 4), (Some(RELAY), 5)]
+        );
     }
 }
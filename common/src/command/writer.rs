@@ -1,9 +1,14 @@
-use crate::{command::commands::HeadlightCommand, types::CommandHeader, CRC};
+use crate::{
+    command::commands::HeadlightCommand,
+    types::{Address, Protocol, Route, V2_MARKER, WAKE_BYTE},
+    CRC,
+};
 use embedded_io_async::{ErrorType, Write};
 use tiny_serde::Serialize;
 
 pub struct HeadlightCommandWriter<HWWriter> {
     tx: HWWriter,
+    protocol: Protocol,
 }
 
 impl<HWWriter> HeadlightCommandWriter<HWWriter>
@@ -11,28 +16,86 @@ where
     HWWriter: Write,
 {
     pub const fn new(tx: HWWriter) -> Self {
-        Self { tx }
+        Self::with_protocol(tx, Protocol::V1)
     }
 
+    pub const fn with_protocol(tx: HWWriter, protocol: Protocol) -> Self {
+        Self { tx, protocol }
+    }
+
+    /// Send a command to the remote device of the link.
     pub async fn send<C, const N: usize>(
         &mut self,
         cmd: C,
     ) -> Result<(), <HWWriter as ErrorType>::Error>
+    where
+        C: HeadlightCommand + Serialize<N>,
+    {
+        let route = match self.protocol {
+            Protocol::V1 => None,
+            Protocol::V2 { local, remote } => Some(Route {
+                dst: remote,
+                src: local,
+            }),
+        };
+
+        self.send_routed(route, cmd).await
+    }
+
+    /// Send a command to `dst` (which may be [`BROADCAST`](crate::types::BROADCAST)).
+    ///
+    /// Point-to-point links have no addresses, so this is the same as [`send`](Self::send).
+    pub async fn send_to<C, const N: usize>(
+        &mut self,
+        dst: Address,
+        cmd: C,
+    ) -> Result<(), <HWWriter as ErrorType>::Error>
+    where
+        C: HeadlightCommand + Serialize<N>,
+    {
+        let route = match self.protocol {
+            Protocol::V1 => None,
+            Protocol::V2 { local, .. } => Some(Route { dst, src: local }),
+        };
+
+        self.send_routed(route, cmd).await
+    }
+
+    /// Wake the remote device from standby, it reports its properties and status once booted.
+    pub async fn wake(&mut self) -> Result<(), <HWWriter as ErrorType>::Error> {
+        self.tx.write_all(&[WAKE_BYTE]).await
+    }
+
+    async fn send_routed<C, const N: usize>(
+        &mut self,
+        route: Option<Route>,
+        cmd: C,
+    ) -> Result<(), <HWWriter as ErrorType>::Error>
     where
         C: HeadlightCommand + Serialize<N>,
     {
         let mut digest = CRC.digest();
 
         let payload = cmd.serialize();
+        let route = route.map(Route::serialize);
+
+        if route.is_some() {
+            digest.update(&[V2_MARKER]);
+        }
         digest.update(&[C::ID]);
+        if let Some(route) = &route {
+            digest.update(route);
+        }
         digest.update(&payload);
 
-        let header = CommandHeader {
-            id: C::ID,
-            crc: digest.finalize(),
-        };
-
-        self.tx.write_all(&header.serialize()).await?;
+        if route.is_some() {
+            self.tx.write_all(&[V2_MARKER]).await?;
+        }
+        self.tx.write_all(&[C::ID]).await?;
+        if let Some(route) = &route {
+            self.tx.write_all(route).await?;
+        }
+        self.tx.write_all(&[digest.finalize()]).await?;
         self.tx.write_all(&payload).await?;
 
         Ok(())
@@ -12,6 +12,9 @@ pub mod properties;
 pub mod types;
 pub mod utils;
 
-use crc::{Crc, CRC_8_AUTOSAR};
+use crc::{Crc, CRC_32_ISO_HDLC, CRC_8_AUTOSAR};
 use types::CRCRepr;
 pub(crate) const CRC: Crc<CRCRepr> = Crc::<CRCRepr>::new(&CRC_8_AUTOSAR);
+
+/// CRC used to validate firmware images end to end.
+pub static IMAGE_CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
@@ -1,17 +1,24 @@
 use crate::{
     command::commands::Properties,
-    types::{Firmware, Hardware, Version},
-    utils::thermistor::celsius_to_sample,
+    types::{Firmware, Hardware, Thermistor, Version},
 };
 
-pub const PROPERTIES: Properties = Properties {
+pub const FIRMWARE: Firmware = Firmware::V0P1;
+
+pub const V2REV3: Properties = Properties {
     version: Version {
         hw: Hardware::V2Rev3,
-        fw: Firmware::V0P1,
+        fw: FIRMWARE,
     },
     abs_max_ma: 1000,
-    abs_max_temp: celsius_to_sample(95),
+    abs_max_temp: Thermistor::Ntc10k.celsius_to_sample(95),
     min_pwm_freq: 50,
     max_pwm_freq: 500,
     max_adc_error: 10,
+    sense_gain: 10,
+    r_shunt: 33,
+    thermistor: Thermistor::Ntc10k,
 };
+
+#[cfg(feature = "hw-v2rev3")]
+pub const PROPERTIES: Properties = V2REV3;
@@ -1,4 +1,8 @@
-use crate::command::commands::*;
+use crate::{
+    command::commands::*,
+    types::ConfigError,
+    utils::{log, validation},
+};
 use tiny_serde::{Deserialize, Serialize};
 
 macro_rules! ser_std_impl {
@@ -23,6 +27,10 @@ ser_std_impl!(Request, serialize_std_request);
 ser_std_impl!(Control, serialize_std_control);
 ser_std_impl!(Config, serialize_std_config);
 ser_std_impl!(Reset, serialize_std_reset);
+ser_std_impl!(ClearFault, serialize_std_clear_fault);
+ser_std_impl!(FirmwareBegin, serialize_std_firmware_begin);
+ser_std_impl!(FirmwareCommit, serialize_std_firmware_commit);
+ser_std_impl!(TimeSync, serialize_std_time_sync);
 
 de_std_impl!(Properties, deserialize_std_properties);
 de_std_impl!(AppError, deserialize_std_app_error);
@@ -30,3 +38,75 @@ de_std_impl!(Status, deserialize_std_status);
 de_std_impl!(Control, deserialize_std_control);
 de_std_impl!(Monitor, deserialize_std_monitor);
 de_std_impl!(Config, deserialize_std_config);
+de_std_impl!(Derating, deserialize_std_derating);
+de_std_impl!(DfuProgress, deserialize_std_dfu_progress);
+de_std_impl!(LinkStatus, deserialize_std_link_status);
+de_std_impl!(Beacon, deserialize_std_beacon);
+de_std_impl!(InputEvent, deserialize_std_input_event);
+de_std_impl!(SignalEvent, deserialize_std_signal_event);
+de_std_impl!(AmbientEvent, deserialize_std_ambient_event);
+de_std_impl!(Log, deserialize_std_log);
+
+/// The final chunk of an image may be short, it is padded with `0xff`.
+#[uniffi::export]
+fn serialize_std_dfu_chunk(offset: u32, data: Vec<u8>) -> Option<Vec<u8>> {
+    if data.len() > DFU_CHUNK_SIZE {
+        return None;
+    }
+
+    let mut padded = [0xff; DFU_CHUNK_SIZE];
+    padded[..data.len()].copy_from_slice(&data);
+
+    Some(
+        DfuChunk {
+            offset,
+            data: padded,
+        }
+        .serialize()
+        .into(),
+    )
+}
+
+/// Missing rules are left empty.
+#[uniffi::export]
+fn serialize_std_schedule(rules: Vec<Rule>) -> Option<Vec<u8>> {
+    if rules.len() > MAX_RULES {
+        return None;
+    }
+
+    let mut schedule = Schedule::default();
+    schedule[..rules.len()].copy_from_slice(&rules);
+
+    Some(serialize_schedule(&schedule).into())
+}
+
+#[uniffi::export]
+fn deserialize_std_schedule(buf: Vec<u8>) -> Option<Vec<Rule>> {
+    Some(deserialize_schedule(buf.try_into().ok()?)?.into())
+}
+
+/// A line describing a log entry, for decoding logs on the host.
+#[uniffi::export]
+pub fn describe_std_log(entry: &Log) -> String {
+    let description = log::description(entry.module, entry.code)
+        .map_or_else(|| format!("unknown code {}", entry.code), String::from);
+
+    format!(
+        "{} +{}ms {:?} {:?} {:?}: {} ({}, {})",
+        entry.time,
+        entry.uptime,
+        entry.source,
+        entry.level,
+        entry.module,
+        description,
+        entry.arg0,
+        entry.arg1
+    )
+}
+
+#[uniffi::export]
+fn validate_config(config: &Config, properties: &Properties) -> Vec<ConfigError> {
+    validation::validate_config(config, properties)
+        .into_iter()
+        .collect()
+}
@@ -7,15 +7,90 @@ use defmt::Format;
 pub type CRCRepr = u8;
 pub type CommandID = u8;
 
-#[derive(Serialize, Deserialize)]
+pub type Address = u8;
+
+/// Commands addressed here are accepted by every device on the bus.
+pub const BROADCAST: Address = 0xff;
+
+/// The address of a headlight on a point-to-point link, which frames commands with [`Protocol::V1`].
+pub const POINT_TO_POINT: Address = 0x00;
+
+/// The relay's address on a bus.
+pub const RELAY: Address = 0x01;
+
+/// Starts every [`Protocol::V2`] frame.
+///
+/// Command IDs start at 0x10, so a frame of either version cannot be mistaken for the other.
+pub const V2_MARKER: u8 = 0x02;
+
+/// Sent to wake a headlight in standby, whose UART is off, so the byte itself is lost.
+pub const WAKE_BYTE: u8 = 0x00;
+
+/// How commands are framed on a link.
+#[derive(Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub enum Protocol {
+    /// Point-to-point, the header is `{ id, crc }`.
+    V1,
+    /// Multi-drop, the header is `{ marker, id, dst, src, crc }`.
+    ///
+    /// Commands are sent from `local` to `remote`, and only commands addressed to `local`
+    /// (or broadcast) are received, along with unmarked commands from a [`V1`](Self::V1) peer.
+    V2 { local: Address, remote: Address },
+}
+
+impl Protocol {
+    /// How a headlight at `address` talks to the relay.
+    pub const fn headlight(address: Address) -> Self {
+        match address {
+            POINT_TO_POINT => Self::V1,
+            local => Self::V2 {
+                local,
+                remote: RELAY,
+            },
+        }
+    }
+
+    /// How the relay talks to the headlight at `address`.
+    pub const fn relay(address: Address) -> Self {
+        match address {
+            POINT_TO_POINT => Self::V1,
+            remote => Self::V2 {
+                local: RELAY,
+                remote,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct Route {
+    pub dst: Address,
+    pub src: Address,
+}
+
+impl Route {
+    /// Whether a command with this route is meant for `local`.
+    pub const fn reaches(&self, local: Address) -> bool {
+        self.dst == local || self.dst == BROADCAST
+    }
+}
+
+/// The route is only present in [`Protocol::V2`] (after the marker),
+/// and is covered by the CRC along with the marker, ID and payload.
 pub struct CommandHeader {
     pub id: CommandID,
+    pub route: Option<Route>,
     pub crc: CRCRepr,
 }
 
 #[derive(Clone, Copy, Serialize, Deserialize)]
 #[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
 #[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
 #[repr(u8)]
 pub enum ConfigError {
     Gain,
@@ -23,22 +98,37 @@ pub enum ConfigError {
     MaxTarget,
     StartupTarget,
     ThrottleBounds,
+    /// a preset or the high beam exceeds the max target
+    InputTargets,
+    /// the turn dimming is over 100% or the brake boost exceeds the max target
+    SignalBehavior,
+    /// a day/night target exceeds the max target or the light levels leave no hysteresis
+    AmbientBehavior,
+    /// the bus address is the relay's or the broadcast address
+    Address,
 }
 
 #[derive(Clone, Copy, Serialize, Deserialize)]
 #[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
 #[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
 #[repr(u8)]
 pub enum RuntimeError {
     Flash = 0x10,
     Overcurrent = 0x20,
     Overtemperature,
+    /// the load conducts, but current is low at max duty (supply voltage is too low)
     InvariantLoad,
     ArithmeticError,
+    /// no current flows through the load (broken connector or load)
+    OpenLoad,
+    /// current rises far faster with duty than the load allows
+    ShortCircuit,
 }
 
 #[derive(Clone, Copy, Default, Serialize, Deserialize)]
 #[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
 #[repr(u8)]
 /// cannot be named "Error" because of Swift :/
 pub enum HeadlightError {
@@ -64,19 +154,325 @@ impl From<RuntimeError> for HeadlightError {
     }
 }
 
+impl HeadlightError {
+    /// The bit representing this error in [`Faults`].
+    ///
+    /// Config errors occupy the lower half and runtime errors the upper half.
+    pub const fn mask(&self) -> u32 {
+        match self {
+            Self::None => 0,
+            Self::Config { e } => 1 << (*e as u8),
+            Self::Runtime { e } => {
+                1 << (16
+                    + match e {
+                        RuntimeError::Flash => 0,
+                        RuntimeError::Overcurrent => 1,
+                        RuntimeError::Overtemperature => 2,
+                        RuntimeError::InvariantLoad => 3,
+                        RuntimeError::ArithmeticError => 4,
+                        RuntimeError::OpenLoad => 5,
+                        RuntimeError::ShortCircuit => 6,
+                    })
+            }
+        }
+    }
+}
+
+/// Set of active warnings and faults, one bit per error.
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+pub struct Faults {
+    pub bits: u32,
+}
+
+impl Faults {
+    pub const NONE: Self = Self { bits: 0 };
+    pub const ALL: Self = Self { bits: u32::MAX };
+
+    pub fn insert(&mut self, error: HeadlightError) {
+        self.bits |= error.mask();
+    }
+
+    pub fn remove(&mut self, faults: Faults) {
+        self.bits &= !faults.bits;
+    }
+
+    pub const fn contains(&self, error: HeadlightError) -> bool {
+        self.bits & error.mask() != 0
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
 #[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
 #[repr(u8)]
 pub enum Mode {
     #[default]
     Idle = 0xf0,
+    /// regulation is parked and the headlight sleeps until woken by UART activity or an input
+    Standby = 0xf1,
     Running = 0xfa,
     Throttling = 0xf2,
     Fault = 0xf3,
 }
 
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[repr(u8)]
+pub enum FirmwareError {
+    #[default]
+    None = 0x00,
+    /// a chunk or commit was received without a preceding begin
+    NotStarted = 0x10,
+    /// the image does not fit in the staging area
+    TooLarge = 0x11,
+    /// a chunk did not continue where the previous one ended
+    OutOfOrder = 0x12,
+    /// the image was committed before every byte was received
+    Incomplete = 0x13,
+    /// the received image does not match the announced CRC
+    Crc = 0x14,
+    Flash = 0x20,
+    /// the bootloader state does not permit an update (previous update not yet confirmed)
+    BadState = 0x21,
+    /// the headlight stopped acknowledging chunks
+    Timeout = 0x30,
+}
+
+/// Progress of an over-the-air update through the relay.
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[repr(u8)]
+pub enum DfuStage {
+    #[default]
+    Idle,
+    /// the image is being received from the app
+    Receiving,
+    /// the buffered image is being checked against its CRC
+    Verifying,
+    /// the image is being sent to the headlight
+    Pushing,
+    /// the headlight accepted the image and is resetting into it
+    Done,
+}
+
+/// What a button gesture does.
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(u8)]
+pub enum InputAction {
+    #[default]
+    None,
+    /// turn the light off, or back on to the target it had before
+    Toggle,
+    /// step to the next preset target
+    CyclePreset,
+    /// briefly switch to the high beam target (for as long as the button is held after a long press)
+    FlashToPass,
+}
+
+/// Which of the vehicle's headlights a device is (or is connected to).
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(u8)]
+pub enum Side {
+    #[default]
+    Left,
+    Right,
+}
+
+impl Side {
+    pub const BOTH: [Self; 2] = [Self::Left, Self::Right];
+
+    pub const fn other(self) -> Self {
+        match self {
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+        }
+    }
+}
+
+/// An input from the vehicle's own electrical system.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[repr(u8)]
+pub enum VehicleSignal {
+    TurnLeft,
+    TurnRight,
+    Brake,
+    Ignition,
+}
+
+/// Ambient light as judged by the headlight's light sensor.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[repr(u8)]
+pub enum AmbientLight {
+    Day,
+    /// dusk, night or anything else dark enough (like a tunnel)
+    Night,
+}
+
+/// What starts a relay schedule rule's delay.
+///
+/// Apart from [`RuleTrigger::Idle`], triggers come in opposing pairs,
+/// and a pending rule is cancelled by the opposite of its trigger.
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[repr(u8)]
+pub enum RuleTrigger {
+    /// the rule slot is empty
+    #[default]
+    Disabled,
+    /// restarted by every command from the app and every button press or switch change
+    Idle,
+    /// the headlights turned on (from a target of zero)
+    LightsOn,
+    /// the headlights turned off (to a target of zero)
+    LightsOff,
+    IgnitionOn,
+    IgnitionOff,
+    /// ambient light changed to [`AmbientLight::Night`]
+    Dusk,
+    /// ambient light changed to [`AmbientLight::Day`]
+    Dawn,
+}
+
+impl RuleTrigger {
+    pub const fn opposite(self) -> Option<Self> {
+        match self {
+            Self::Disabled | Self::Idle => None,
+            Self::LightsOn => Some(Self::LightsOff),
+            Self::LightsOff => Some(Self::LightsOn),
+            Self::IgnitionOn => Some(Self::IgnitionOff),
+            Self::IgnitionOff => Some(Self::IgnitionOn),
+            Self::Dusk => Some(Self::Dawn),
+            Self::Dawn => Some(Self::Dusk),
+        }
+    }
+}
+
+/// What a relay schedule rule does once its delay has passed, to both headlights.
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[repr(u8)]
+pub enum RuleAction {
+    /// send a control with the rule's target (zero to turn off)
+    #[default]
+    SetTarget,
+    Standby,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[repr(u8)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+}
+
+/// The part of the firmware a log entry comes from, each has its own codes (see `utils::log`).
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[repr(u8)]
+pub enum LogModule {
+    Fault,
+    Config,
+    Update,
+    Input,
+    Signal,
+    Ambient,
+    Standby,
+    Time,
+    Link,
+    Schedule,
+}
+
+/// The device a log entry comes from.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[repr(u8)]
+pub enum LogSource {
+    Relay,
+    /// set by the headlight, which does not know its side
+    Headlight,
+    /// set by the relay when forwarding
+    LeftHeadlight,
+    RightHeadlight,
+}
+
+impl From<Side> for LogSource {
+    fn from(value: Side) -> Self {
+        match value {
+            Side::Left => Self::LeftHeadlight,
+            Side::Right => Self::RightHeadlight,
+        }
+    }
+}
+
+/// A physical input recognized by the headlight.
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[repr(u8)]
+pub enum Gesture {
+    ShortPress,
+    LongPress,
+    DoublePress,
+    /// the high/low switch moved to high
+    HighBeam,
+    /// the high/low switch moved to low
+    LowBeam,
+}
+
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(feature = "defmt", derive(Format))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[repr(u8)]
+pub enum DeratingReason {
+    #[default]
+    None,
+    Temperature,
+    /// reserved for supply voltage derating
+    Supply,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
 #[repr(u8)]
 pub enum Hardware {
     V2Rev0,
@@ -84,15 +480,46 @@ pub enum Hardware {
     V2Rev3,
 }
 
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
+#[repr(u8)]
+pub enum Thermistor {
+    /// 10k NTC on the FET heatsink (all V2 revisions)
+    Ntc10k,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[cfg_attr(not(target_os = "none"), derive(uniffi::Enum))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
 #[repr(u8)]
 pub enum Firmware {
     V0P1,
 }
 
+impl Hardware {
+    /// Human readable revision, as reported by the relay's Device Information Service.
+    pub const fn revision(&self) -> &'static str {
+        match self {
+            Self::V2Rev0 => "V2 Rev0",
+            Self::V2Rev1 => "V2 Rev1",
+            Self::V2Rev3 => "V2 Rev3",
+        }
+    }
+}
+
+impl Firmware {
+    /// Human readable revision, as reported by the relay's Device Information Service.
+    pub const fn revision(&self) -> &'static str {
+        match self {
+            Self::V0P1 => "0.1",
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 #[cfg_attr(not(target_os = "none"), derive(uniffi::Record))]
+#[cfg_attr(not(target_os = "none"), derive(Debug))]
 pub struct Version {
     pub hw: Hardware,
     pub fw: Firmware,
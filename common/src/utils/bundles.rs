@@ -12,6 +12,14 @@ pub enum FromHeadlightBundle {
     Control(Control),
     Monitor(Monitor),
     Config(Config),
+    Properties(Properties),
+    Derating(Derating),
+    FirmwareStatus(FirmwareStatus),
+    Heartbeat(Heartbeat),
+    InputEvent(InputEvent),
+    SignalEvent(SignalEvent),
+    AmbientEvent(AmbientEvent),
+    Log(Log),
 }
 
 #[bundle(export)]
@@ -20,6 +28,12 @@ pub enum ToHeadlightBundle {
     Control(Control),
     Config(Config),
     Reset(Reset),
+    ClearFault(ClearFault),
+    FirmwareBegin(FirmwareBegin),
+    FirmwareChunk(FirmwareChunk),
+    FirmwareCommit(FirmwareCommit),
+    Heartbeat(Heartbeat),
+    TimeSync(TimeSync),
 }
 
 macro_rules! impl_parse {
@@ -0,0 +1,64 @@
+use crate::command::commands::TimeSync;
+
+/// Syncs closer together than this are too short to measure drift over.
+const MIN_DRIFT_INTERVAL_MS: u64 = 10 * 60 * 1000;
+/// Crystals are far better than this, anything beyond is a bad sync.
+const MAX_DRIFT_PPM: i64 = 500;
+
+/// Wall-clock time kept on top of the uptime clock, from syncs by the app.
+///
+/// Uptime is passed in (in milliseconds) so the clock does not depend on a timer driver.
+/// It is lost on reset, so it must be synced again after every boot.
+pub struct WallClock {
+    /// Unix time and uptime at the latest sync (ms)
+    anchor: Option<(u64, u64)>,
+    /// How far the uptime clock runs fast, in parts per million (negative if slow)
+    drift_ppm: i64,
+}
+
+impl WallClock {
+    pub const fn new() -> Self {
+        Self {
+            anchor: None,
+            drift_ppm: 0,
+        }
+    }
+
+    /// Adopt the synced time, measuring the uptime clock's drift since the previous sync.
+    pub fn sync(&mut self, time: &TimeSync, uptime_ms: u64) {
+        let unix_ms = u64::from(time.unix) * 1000 + u64::from(time.millis);
+
+        if let Some((prev_unix_ms, prev_uptime_ms)) = self.anchor {
+            let real = unix_ms as i64 - prev_unix_ms as i64;
+            let measured = uptime_ms as i64 - prev_uptime_ms as i64;
+
+            if real >= MIN_DRIFT_INTERVAL_MS as i64 {
+                let drift_ppm = (measured - real) * 1_000_000 / real;
+
+                if drift_ppm.abs() <= MAX_DRIFT_PPM {
+                    self.drift_ppm = drift_ppm;
+                }
+            }
+        }
+
+        self.anchor = Some((unix_ms, uptime_ms));
+    }
+
+    /// The current time, if the clock has been synced.
+    pub fn now(&self, uptime_ms: u64) -> Option<TimeSync> {
+        let (unix_ms, at_ms) = self.anchor?;
+        let elapsed = uptime_ms.saturating_sub(at_ms) as i64;
+        let corrected = elapsed - elapsed * self.drift_ppm / 1_000_000;
+        let now_ms = unix_ms.saturating_add_signed(corrected);
+
+        Some(TimeSync {
+            unix: u32::try_from(now_ms / 1000).ok()?,
+            millis: (now_ms % 1000) as u16,
+        })
+    }
+
+    /// The current Unix time in seconds, or 0 if the clock has not been synced.
+    pub fn timestamp(&self, uptime_ms: u64) -> u32 {
+        self.now(uptime_ms).map_or(0, |time| time.unix)
+    }
+}
@@ -0,0 +1,115 @@
+use heapless::Deque;
+
+use crate::{command::commands::Log, types::LogModule};
+
+/// Codes of each [`LogModule`], with the meaning of their arguments.
+pub mod codes {
+    pub mod fault {
+        /// arg0: the error's bit in `Faults`
+        pub const RAISED: u8 = 0;
+        /// arg0: the bits cleared
+        pub const CLEARED: u8 = 1;
+    }
+
+    pub mod config {
+        pub const WRITTEN: u8 = 0;
+        pub const WRITE_FAILED: u8 = 1;
+    }
+
+    pub mod update {
+        /// arg0: image size, arg1: `Side` (only from the relay, pushing the image to that side)
+        pub const BEGUN: u8 = 0;
+        pub const COMMITTED: u8 = 1;
+        /// arg0: `FirmwareError`
+        pub const FAILED: u8 = 2;
+    }
+
+    pub mod input {
+        /// arg0: `Gesture`, arg1: `InputAction`
+        pub const GESTURE: u8 = 0;
+    }
+
+    pub mod signal {
+        /// arg0: `VehicleSignal`, arg1: whether it is active
+        pub const CHANGED: u8 = 0;
+    }
+
+    pub mod ambient {
+        /// arg0: `AmbientLight`, arg1: light level
+        pub const CHANGED: u8 = 0;
+    }
+
+    pub mod standby {
+        pub const ENTERED: u8 = 0;
+    }
+
+    pub mod time {
+        /// arg0: Unix time
+        pub const SYNCED: u8 = 0;
+    }
+
+    pub mod link {
+        /// arg0: `Side`
+        pub const ESTABLISHED: u8 = 0;
+        /// arg0: `Side`
+        pub const LOST: u8 = 1;
+    }
+
+    pub mod schedule {
+        /// arg0: rule index, arg1: `RuleAction`
+        pub const EXECUTED: u8 = 0;
+        pub const STORED: u8 = 1;
+    }
+}
+
+/// What a code means, for decoding on the host.
+pub fn description(module: LogModule, code: u8) -> Option<&'static str> {
+    use codes::*;
+
+    Some(match (module, code) {
+        (LogModule::Fault, fault::RAISED) => "fault raised",
+        (LogModule::Fault, fault::CLEARED) => "faults cleared",
+        (LogModule::Config, config::WRITTEN) => "config written",
+        (LogModule::Config, config::WRITE_FAILED) => "config write failed",
+        (LogModule::Update, update::BEGUN) => "firmware update begun",
+        (LogModule::Update, update::COMMITTED) => "firmware update committed",
+        (LogModule::Update, update::FAILED) => "firmware update failed",
+        (LogModule::Input, input::GESTURE) => "input gesture",
+        (LogModule::Signal, signal::CHANGED) => "vehicle signal changed",
+        (LogModule::Ambient, ambient::CHANGED) => "ambient light changed",
+        (LogModule::Standby, standby::ENTERED) => "entering standby",
+        (LogModule::Time, time::SYNCED) => "time synced",
+        (LogModule::Link, link::ESTABLISHED) => "headlight link established",
+        (LogModule::Link, link::LOST) => "headlight link lost",
+        (LogModule::Schedule, schedule::EXECUTED) => "schedule rule executed",
+        (LogModule::Schedule, schedule::STORED) => "schedule stored",
+        _ => return None,
+    })
+}
+
+/// The most recent log entries, the oldest are overwritten once full.
+pub struct LogRing<const N: usize> {
+    entries: Deque<Log, N>,
+}
+
+impl<const N: usize> LogRing<N> {
+    pub const fn new() -> Self {
+        Self {
+            entries: Deque::new(),
+        }
+    }
+
+    pub fn push(&mut self, entry: Log) {
+        if self.entries.is_full() {
+            self.entries.pop_front();
+        }
+
+        // cannot fail, there is room after popping
+        self.entries.push_back(entry).ok();
+    }
+
+    /// Oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Log> {
+        self.entries.iter()
+    }
+}
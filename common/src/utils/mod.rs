@@ -1,4 +1,7 @@
 pub mod assign_resources;
 pub mod bundles;
+pub mod clock;
+pub mod log;
 pub(crate) mod scan_buf;
 pub mod thermistor;
+pub mod validation;
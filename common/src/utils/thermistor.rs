@@ -1,5 +1,7 @@
+use crate::types::Thermistor;
+
 // precomputed
-const LUT: [u16; 20] = [
+const NTC_10K_LUT: [u16; 20] = [
     111, // 0C
     144, // 5C
     186, // ...
@@ -8,28 +10,50 @@ const LUT: [u16; 20] = [
     2511, // 95C
 ];
 
-pub const fn celsius_to_sample(celsius: u8) -> u16 {
-    LUT[(celsius / 5) as usize]
-}
+impl Thermistor {
+    const fn lut(&self) -> &'static [u16; 20] {
+        match self {
+            Self::Ntc10k => &NTC_10K_LUT,
+        }
+    }
 
-#[cfg(not(target_os = "none"))]
-#[uniffi::export]
-fn sample_to_celsius(sample: u16) -> u8 {
-    match LUT.binary_search(&sample) {
-        Ok(index) => u8::try_from(index).unwrap() * 5,
-        Err(index) => {
-            if index == 0 {
-                0
-            } else {
-                let lower = LUT[index - 1];
-                let upper = LUT[index];
-
-                u8::try_from(
-                    5 * (sample - lower) / (upper - lower)
-                        + 5 * (u16::try_from(index).unwrap() - 1),
-                )
-                .unwrap()
+    pub const fn celsius_to_sample(&self, celsius: u8) -> u16 {
+        self.lut()[(celsius / 5) as usize]
+    }
+
+    /// Like [`Self::celsius_to_sample`], but returns `None` if the temperature is out of range.
+    pub fn checked_celsius_to_sample(&self, celsius: u8) -> Option<u16> {
+        self.lut().get(usize::from(celsius / 5)).copied()
+    }
+
+    pub fn sample_to_celsius(&self, sample: u16) -> u8 {
+        let lut = self.lut();
+
+        match lut.binary_search(&sample) {
+            Ok(index) => u8::try_from(index).unwrap() * 5,
+            Err(index) => {
+                if index == 0 {
+                    0
+                } else if index == lut.len() {
+                    // saturate beyond the table
+                    u8::try_from(lut.len() - 1).unwrap() * 5
+                } else {
+                    let lower = lut[index - 1];
+                    let upper = lut[index];
+
+                    u8::try_from(
+                        5 * (sample - lower) / (upper - lower)
+                            + 5 * (u16::try_from(index).unwrap() - 1),
+                    )
+                    .unwrap()
+                }
             }
         }
     }
 }
+
+#[cfg(not(target_os = "none"))]
+#[uniffi::export]
+fn sample_to_celsius(thermistor: Thermistor, sample: u16) -> u8 {
+    thermistor.sample_to_celsius(sample)
+}
@@ -0,0 +1,80 @@
+use heapless::Vec;
+
+use crate::{
+    command::commands::{Config, Properties},
+    types::{ConfigError, BROADCAST, RELAY},
+};
+
+/// Number of distinct rules a config is checked against.
+pub const CONFIG_RULES: usize = 9;
+
+pub type ConfigErrors = Vec<ConfigError, CONFIG_RULES>;
+
+/// Checks a config against the properties of the device it is destined for.
+///
+/// Every violated rule is reported, not just the first.
+pub fn validate_config(config: &Config, properties: &Properties) -> ConfigErrors {
+    let mut errors = ConfigErrors::new();
+    let mut check = |valid: bool, e: ConfigError| {
+        if !valid {
+            // capacity is the number of rules, so this cannot fail
+            errors.push(e).ok();
+        }
+    };
+
+    check(
+        (properties.min_pwm_freq..=properties.max_pwm_freq).contains(&config.pwm_freq),
+        ConfigError::PWMFreq,
+    );
+
+    check(
+        config.abs_max_load_current < properties.abs_max_ma,
+        ConfigError::MaxTarget,
+    );
+
+    check(
+        config.startup_control.target <= config.max_target_current,
+        ConfigError::StartupTarget,
+    );
+
+    check(config.gain >= 1, ConfigError::Gain);
+
+    check(
+        config.throttle_start < config.throttle_stop
+            && properties
+                .thermistor
+                .checked_celsius_to_sample(config.throttle_stop)
+                .is_some_and(|stop| stop < properties.abs_max_temp),
+        ConfigError::ThrottleBounds,
+    );
+
+    check(
+        config
+            .input
+            .presets
+            .targets()
+            .iter()
+            .chain([config.input.high_beam].iter())
+            .all(|&target| target <= config.max_target_current),
+        ConfigError::InputTargets,
+    );
+
+    check(
+        config.signals.turn_dim <= 100 && config.signals.brake_boost <= config.max_target_current,
+        ConfigError::SignalBehavior,
+    );
+
+    check(
+        config.ambient.day_target <= config.max_target_current
+            && config.ambient.night_target <= config.max_target_current
+            && config.ambient.dusk_level < config.ambient.dawn_level,
+        ConfigError::AmbientBehavior,
+    );
+
+    check(
+        config.address != RELAY && config.address != BROADCAST,
+        ConfigError::Address,
+    );
+
+    errors
+}
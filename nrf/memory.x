@@ -2,6 +2,12 @@ MEMORY
 {
   /* NOTE 1 K = 1 KiBi = 1024 bytes */
   /* These values correspond to the NRF52840 with Softdevices S140 7.0.1 */
-  FLASH : ORIGIN = 0x00027000, LENGTH = 868K
+  FLASH : ORIGIN = 0x00027000, LENGTH = 844K
+  /* persistent relay data (schedule, bonds, identity), must match `utils::storage` */
+  STORAGE : ORIGIN = 0x000FA000, LENGTH = 12K
+  /* headlight images received over BLE are buffered here, must match `utils::dfu` */
+  STAGING : ORIGIN = 0x000FD000, LENGTH = 12K
+  /* the SoftDevice gets the first 128K, far more than its config (attribute table included) needs,
+     if it ever asks for more, it logs the RAM start it requires when enabled */
   RAM : ORIGIN = 0x20020000, LENGTH = 128K
 }
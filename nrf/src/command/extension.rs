@@ -1,13 +1,22 @@
-use crate::utils::ble::Server;
-use common::command::commands::*;
+use crate::utils::{
+    beacon::update_beacon,
+    ble::{gatt_string, side_service, Server},
+    dfu::firmware_status,
+    log,
+};
+use common::{command::commands::*, types::Side};
 #[cfg(feature = "defmt")]
 use defmt::Format;
-use nrf_softdevice::ble::{gatt_server::NotifyValueError, Connection};
-use tiny_serde::Serialize;
+use nrf_softdevice::ble::{
+    gatt_server::{NotifyValueError, SetValueError},
+    Connection,
+};
+use tiny_serde::{Deserialize, Serialize};
 
 #[cfg_attr(feature = "defmt", derive(Format))]
 pub enum CommandExecutionError {
     NotifyValueError(NotifyValueError),
+    SetValueError(SetValueError),
 }
 
 impl From<NotifyValueError> for CommandExecutionError {
@@ -16,38 +25,309 @@ impl From<NotifyValueError> for CommandExecutionError {
     }
 }
 
+impl From<SetValueError> for CommandExecutionError {
+    fn from(value: SetValueError) -> Self {
+        Self::SetValueError(value)
+    }
+}
+
+/// Notify every connection, skipping any that fail (e.g. have not subscribed).
+fn notify_all(conns: &[Connection], notify: impl Fn(&Connection) -> Result<(), NotifyValueError>) {
+    for conn in conns {
+        notify(conn).ok();
+    }
+}
+
+/// Notify what `$service` has cached, if anything.
+macro_rules! notify_cached_all {
+    ($service:expr, $get:ident, $notify:ident, $conns:expr) => {
+        if let Ok(data) = $service.$get() {
+            notify_all($conns, |conn| $service.$notify(conn, &data));
+        }
+    };
+}
+
+/// Answer `request` for a headlight in standby from what it last reported, rather than waking it.
+///
+/// Nothing is measured or logged in standby, and the properties are only ever read.
+pub fn answer_from_cache(server: &Server, conns: &[Connection], side: Side, request: &Request) {
+    match request {
+        Request::Status => {
+            notify_cached_all!(server.headlight, status_get, status_notify, conns);
+            side_service!(server, side, |service| notify_cached_all!(
+                service,
+                status_get,
+                status_notify,
+                conns
+            ));
+        }
+        Request::Control => {
+            notify_cached_all!(server.headlight, control_get, control_notify, conns);
+            side_service!(server, side, |service| notify_cached_all!(
+                service,
+                control_get,
+                control_notify,
+                conns
+            ));
+        }
+        Request::Config => {
+            notify_cached_all!(server.headlight, config_get, config_notify, conns);
+        }
+        Request::Derating => {
+            notify_cached_all!(server.headlight, derating_get, derating_notify, conns);
+        }
+        Request::Monitor | Request::Properties | Request::Log => {}
+    }
+}
+
+/// Received state is always cached in the characteristic value so it can be read at any time,
+/// and is notified to every connected phone.
+///
+/// State reported per headlight is cached both in the service of the `side` it came from,
+/// and in the combined service (which therefore holds whichever side reported last).
+/// Every cache is written before anything is notified,
+/// so a central that has not subscribed cannot leave one stale.
 pub trait Execute {
-    fn run(self, server: &Server, conn: &Connection) -> Result<(), CommandExecutionError>;
+    fn run(
+        self,
+        server: &Server,
+        conns: &[Connection],
+        side: Side,
+    ) -> Result<(), CommandExecutionError>;
 }
 
 impl Execute for Status {
-    fn run(self, server: &Server, conn: &Connection) -> Result<(), CommandExecutionError> {
-        server.headlight.status_notify(&conn, &self.serialize())?;
+    fn run(
+        self,
+        server: &Server,
+        conns: &[Connection],
+        side: Side,
+    ) -> Result<(), CommandExecutionError> {
+        update_beacon(|beacon| {
+            beacon.mode = self.mode;
+            beacon.latest_fault = self.latest_fault;
+        });
+
+        let data = self.serialize();
+
+        server.headlight.status_set(&data)?;
+        side_service!(server, side, |service| service.status_set(&data))?;
+
+        notify_all(conns, |conn| server.headlight.status_notify(conn, &data));
+        side_service!(server, side, |service| {
+            notify_all(conns, |conn| service.status_notify(conn, &data))
+        });
 
         Ok(())
     }
 }
 
 impl Execute for Control {
-    fn run(self, server: &Server, conn: &Connection) -> Result<(), CommandExecutionError> {
-        server.headlight.control_notify(&conn, &self.serialize())?;
+    fn run(
+        self,
+        server: &Server,
+        conns: &[Connection],
+        side: Side,
+    ) -> Result<(), CommandExecutionError> {
+        let data = self.serialize();
+
+        server.headlight.control_set(&data)?;
+        side_service!(server, side, |service| service.control_set(&data))?;
+
+        notify_all(conns, |conn| server.headlight.control_notify(conn, &data));
+        side_service!(server, side, |service| {
+            notify_all(conns, |conn| service.control_notify(conn, &data))
+        });
 
         Ok(())
     }
 }
 
 impl Execute for Monitor {
-    fn run(self, server: &Server, conn: &Connection) -> Result<(), CommandExecutionError> {
-        server.headlight.monitor_notify(&conn, &self.serialize())?;
+    fn run(
+        self,
+        server: &Server,
+        conns: &[Connection],
+        side: Side,
+    ) -> Result<(), CommandExecutionError> {
+        // converting the raw sample requires the headlight's thermistor
+        if let Some(properties) = server
+            .headlight
+            .properties_get()
+            .ok()
+            .and_then(Properties::deserialize)
+        {
+            let celsius = properties.thermistor.sample_to_celsius(self.temperature);
+            let temperature = celsius as i16 * 100;
+
+            update_beacon(|beacon| beacon.temperature = celsius);
+
+            server.environmental_sensing.temperature_set(&temperature)?;
+
+            notify_all(conns, |conn| {
+                server
+                    .environmental_sensing
+                    .temperature_notify(conn, &temperature)
+            });
+        }
+
+        let data = self.serialize();
+
+        server.headlight.monitor_set(&data)?;
+        side_service!(server, side, |service| service.monitor_set(&data))?;
+
+        notify_all(conns, |conn| server.headlight.monitor_notify(conn, &data));
+        side_service!(server, side, |service| {
+            notify_all(conns, |conn| service.monitor_notify(conn, &data))
+        });
 
         Ok(())
     }
 }
 
 impl Execute for Config {
-    fn run(self, server: &Server, conn: &Connection) -> Result<(), CommandExecutionError> {
-        server.headlight.config_notify(&conn, &self.serialize())?;
+    fn run(
+        self,
+        server: &Server,
+        conns: &[Connection],
+        _side: Side,
+    ) -> Result<(), CommandExecutionError> {
+        // both sides share a config, apart from the side the relay sets for each
+        let data = self.serialize();
+
+        server.headlight.config_set(&data)?;
+
+        notify_all(conns, |conn| server.headlight.config_notify(conn, &data));
+
+        Ok(())
+    }
+}
+
+impl Execute for Derating {
+    fn run(
+        self,
+        server: &Server,
+        conns: &[Connection],
+        _side: Side,
+    ) -> Result<(), CommandExecutionError> {
+        update_beacon(|beacon| beacon.target = self.effective_target);
+
+        let data = self.serialize();
+
+        server.headlight.derating_set(&data)?;
+
+        notify_all(conns, |conn| server.headlight.derating_notify(conn, &data));
+
+        Ok(())
+    }
+}
+
+impl Execute for Properties {
+    fn run(
+        self,
+        server: &Server,
+        _conns: &[Connection],
+        _side: Side,
+    ) -> Result<(), CommandExecutionError> {
+        let info = &server.device_information;
+
+        info.hardware_revision_set(&gatt_string(self.version.hw.revision()))?;
+        info.firmware_revision_set(&gatt_string(self.version.fw.revision()))?;
+
+        // properties are static, so they are kept readable rather than notified
+        server.headlight.properties_set(&self.serialize())?;
+
+        Ok(())
+    }
+}
+
+impl Execute for FirmwareStatus {
+    fn run(
+        self,
+        _server: &Server,
+        _conns: &[Connection],
+        side: Side,
+    ) -> Result<(), CommandExecutionError> {
+        firmware_status(side).signal(self);
+
+        Ok(())
+    }
+}
+
+impl Execute for InputEvent {
+    fn run(
+        self,
+        server: &Server,
+        conns: &[Connection],
+        _side: Side,
+    ) -> Result<(), CommandExecutionError> {
+        // events are momentary, so they are not cached
+        let data = self.serialize();
+
+        notify_all(conns, |conn| {
+            server.headlight.input_event_notify(conn, &data)
+        });
+
+        Ok(())
+    }
+}
+
+impl Execute for SignalEvent {
+    fn run(
+        self,
+        server: &Server,
+        conns: &[Connection],
+        _side: Side,
+    ) -> Result<(), CommandExecutionError> {
+        let data = self.serialize();
+
+        notify_all(conns, |conn| {
+            server.headlight.signal_event_notify(conn, &data)
+        });
+
+        Ok(())
+    }
+}
+
+impl Execute for AmbientEvent {
+    fn run(
+        self,
+        server: &Server,
+        conns: &[Connection],
+        _side: Side,
+    ) -> Result<(), CommandExecutionError> {
+        let data = self.serialize();
+
+        notify_all(conns, |conn| {
+            server.headlight.ambient_event_notify(conn, &data)
+        });
+
+        Ok(())
+    }
+}
+
+impl Execute for Log {
+    fn run(
+        self,
+        _server: &Server,
+        _conns: &[Connection],
+        side: Side,
+    ) -> Result<(), CommandExecutionError> {
+        // notified by the log worker along with the relay's own entries
+        log::forward(side, self);
+
+        Ok(())
+    }
+}
 
+impl Execute for Heartbeat {
+    fn run(
+        self,
+        _server: &Server,
+        _conns: &[Connection],
+        _side: Side,
+    ) -> Result<(), CommandExecutionError> {
+        // the echo has already been counted by the link monitor
         Ok(())
     }
 }
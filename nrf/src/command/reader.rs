@@ -1,30 +1,81 @@
 use crate::{
-    command::extension::Execute,
+    command::{extension::Execute, writer::Headlights},
     fmt::warn,
-    utils::{ble::BLE, uart::BUF_SIZE},
+    utils::{
+        ble::BLE,
+        clock,
+        link::LinkMonitor,
+        schedule::observe_headlight,
+        security::observe_for_pairing,
+        uart::{LeftRx, RightRx, BUF_SIZE},
+    },
 };
-use common::{command::reader::*, use_from_headlight_bundle, utils::bundles::FromHeadlightBundle};
-use embassy_nrf::{
-    buffered_uarte::BufferedUarteRx,
-    peripherals::{TIMER1, UARTE0},
+use common::{
+    command::reader::*, types::Side, use_from_headlight_bundle, utils::bundles::FromHeadlightBundle,
 };
 
-#[embassy_executor::task]
-pub async fn receive_command_worker(
-    mut reader: HeadlightCommandReader<BufferedUarteRx<'static, 'static, UARTE0, TIMER1>, BUF_SIZE>,
-    ble: &'static BLE,
-) {
-    reader
-        .dispatch(|bundle| async {
-            use_from_headlight_bundle!(bundle, |cmd| {
-                if let Some(conn) = ble.get_conn().await {
-                    if let Err(e) = cmd.run(ble.get_server(), &conn) {
-                        warn!("Command failed to dispatch with error: {}", e);
+/// Embassy tasks cannot be generic, so one is generated per link.
+macro_rules! receive_command_worker {
+    ($name:ident, $rx:ty, $side:expr) => {
+        #[embassy_executor::task]
+        pub async fn $name(
+            mut reader: HeadlightCommandReader<$rx, BUF_SIZE, &'static LinkMonitor>,
+            ble: &'static BLE,
+            headlights: &'static Headlights,
+        ) {
+            let side = $side;
+
+            reader
+                .dispatch(|bundle| async {
+                    observe_headlight(&bundle);
+                    observe_for_pairing(&bundle);
+
+                    let is_status = matches!(bundle, FromHeadlightBundle::Status(_));
+
+                    // a headlight reports its properties on boot, having lost the time
+                    if let FromHeadlightBundle::Properties(_) = &bundle {
+                        if let Some(time) = clock::now() {
+                            headlights.queue(side).send(time.into()).await;
+                        }
+                    }
+
+                    // a headlight that believes it is on the other side is corrected (which resets it)
+                    if let FromHeadlightBundle::Config(config) = &bundle {
+                        if config.signals.side != side {
+                            headlights.queue(side).send(config.clone().into()).await;
+                        }
+                    }
+
+                    // changes made by one headlight (not by the relay) are applied to both
+                    if let FromHeadlightBundle::Control(control) = &bundle {
+                        if headlights.reported_control(side, control) {
+                            headlights
+                                .queue(side.other())
+                                .send(control.clone().into())
+                                .await;
+                        }
+                    }
+
+                    use_from_headlight_bundle!(bundle, |cmd| {
+                        let conns = ble.get_conns().await;
+
+                        if let Err(e) = cmd.run(ble.get_server(), &conns, side) {
+                            warn!(
+                                "Command from {} headlight failed to dispatch with error: {}",
+                                side, e
+                            );
+                        }
+                    });
+
+                    // the status is cached by now, so a command held for the wake may be sent
+                    if is_status {
+                        headlights.reported_status(side);
                     }
-                } else {
-                    warn!("Attempted to dispatch command while BLE client is not connected.")
-                }
-            });
-        })
-        .await;
+                })
+                .await;
+        }
+    };
 }
+
+receive_command_worker!(receive_left_worker, LeftRx, Side::Left);
+receive_command_worker!(receive_right_worker, RightRx, Side::Right);
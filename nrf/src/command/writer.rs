@@ -1,30 +1,228 @@
 use common::{
-    command::writer::HeadlightCommandWriter, use_to_headlight_bundle,
+    command::{commands::Control, writer::HeadlightCommandWriter},
+    types::Side,
+    use_to_headlight_bundle,
     utils::bundles::ToHeadlightBundle,
 };
-use embassy_nrf::{
-    buffered_uarte::BufferedUarteTx,
-    peripherals::{TIMER1, UARTE0},
-};
-use embassy_sync::blocking_mutex::raw::ThreadModeRawMutex;
-use embassy_sync::channel::Channel;
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::{raw::ThreadModeRawMutex, Mutex};
+use embassy_sync::channel::{Channel, TrySendError};
+use embassy_sync::signal::Signal;
+use embassy_time::{with_timeout, Duration};
+use tiny_serde::{prelude::*, Deserialize, Serialize};
 
-use crate::fmt::error;
+use crate::{
+    command::extension::answer_from_cache,
+    fmt::{error, warn},
+    utils::{
+        ble::BLE,
+        link::in_standby,
+        schedule::observe_control,
+        uart::{LeftTx, RightTx},
+    },
+};
 
 pub type WriterQueue = Channel<ThreadModeRawMutex, ToHeadlightBundle, 8>;
 
-#[embassy_executor::task]
-pub async fn send_command_worker(
-    mut writer: HeadlightCommandWriter<BufferedUarteTx<'static, 'static, UARTE0, TIMER1>>,
-    queue: &'static WriterQueue,
-) {
-    loop {
-        let bundle = queue.recv().await;
+type ControlData = [u8; <Control as _TinyDeSized>::SIZE];
+
+/// Fit a command to the headlight on `side`.
+///
+/// The side a headlight is mounted on is the link it is wired to,
+/// so it overrides whatever side the (shared) config was written with.
+fn fit(side: Side, bundle: ToHeadlightBundle) -> ToHeadlightBundle {
+    match bundle {
+        ToHeadlightBundle::Config(mut config) => {
+            config.signals.side = side;
+            config.into()
+        }
+        bundle => bundle,
+    }
+}
+
+/// Whether `bundle` changes the headlight's state, so is worth waking it from standby for.
+///
+/// Requests are answered from the relay's cache instead,
+/// heartbeats would only keep it awake, and the time is sent again once it boots.
+fn changes_state(bundle: &ToHeadlightBundle) -> bool {
+    !matches!(
+        bundle,
+        ToHeadlightBundle::Request(_)
+            | ToHeadlightBundle::Heartbeat(_)
+            | ToHeadlightBundle::TimeSync(_)
+    )
+}
+
+/// How long a woken headlight has to boot and report its status.
+const WAKE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Command queues to both headlights.
+///
+/// The control last sent to (or reported by) each side is remembered,
+/// so changes a headlight makes on its own can be told apart and mirrored to the other side.
+pub struct Headlights {
+    queues: [WriterQueue; 2],
+    controls: Mutex<ThreadModeRawMutex, Cell<[Option<ControlData>; 2]>>,
+    /// Signalled whenever a side reports its status.
+    statuses: [Signal<ThreadModeRawMutex, ()>; 2],
+}
+
+impl Headlights {
+    pub const fn new() -> Self {
+        Self {
+            queues: [WriterQueue::new(), WriterQueue::new()],
+            controls: Mutex::new(Cell::new([None; 2])),
+            statuses: [Signal::new(), Signal::new()],
+        }
+    }
+
+    pub fn queue(&self, side: Side) -> &WriterQueue {
+        &self.queues[side as usize]
+    }
+
+    fn expect(&self, side: Side, bundle: &ToHeadlightBundle) {
+        if let ToHeadlightBundle::Control(control) = bundle {
+            // the headlights do not report controls sent to them
+            observe_control(control);
+
+            let data = control.clone().serialize();
+
+            self.controls.lock(|controls| {
+                let mut new = controls.get();
+                new[side as usize] = Some(data);
+                controls.set(new);
+            });
+        }
+    }
+
+    pub fn try_send(
+        &self,
+        side: Side,
+        bundle: ToHeadlightBundle,
+    ) -> Result<(), TrySendError<ToHeadlightBundle>> {
+        self.expect(side, &bundle);
+        self.queue(side).try_send(bundle)
+    }
+
+    /// Send a command to both headlights, so they stay in lockstep.
+    pub async fn send_both(&self, bundle: ToHeadlightBundle) {
+        for side in Side::BOTH {
+            self.expect(side, &bundle);
+        }
+
+        use_to_headlight_bundle!(bundle, |cmd| {
+            self.queue(Side::Left).send(cmd.clone().into()).await;
+            self.queue(Side::Right).send(cmd.into()).await;
+        })
+    }
+
+    /// Send a command to both headlights without waiting.
+    ///
+    /// Fails without sending anything if either queue is full.
+    pub fn try_send_both(
+        &self,
+        bundle: ToHeadlightBundle,
+    ) -> Result<(), TrySendError<ToHeadlightBundle>> {
+        if Side::BOTH.iter().any(|side| self.queue(*side).is_full()) {
+            return Err(TrySendError::Full(bundle));
+        }
+
+        for side in Side::BOTH {
+            self.expect(side, &bundle);
+        }
 
         use_to_headlight_bundle!(bundle, |cmd| {
-            if let Err(e) = writer.send(cmd).await {
-                error!("Command failed to send with error: {}", e);
+            self.queue(Side::Left).try_send(cmd.clone().into())?;
+            self.queue(Side::Right).try_send(cmd.into())
+        })
+    }
+
+    /// Record that `side` reported its status, which it does once booted.
+    pub fn reported_status(&self, side: Side) {
+        self.statuses[side as usize].signal(());
+    }
+
+    /// The control `side` was last known to have.
+    pub fn control(&self, side: Side) -> Option<Control> {
+        self.controls
+            .lock(|controls| controls.get()[side as usize])
+            .and_then(|data| Control::deserialize(data))
+    }
+
+    /// Record a control reported by `side`, returning whether it must be mirrored to the other side.
+    ///
+    /// Only controls that differ from what the side was last known to have are its own changes,
+    /// the first report after boot is just the side's initial state.
+    pub fn reported_control(&self, side: Side, control: &Control) -> bool {
+        let data = control.clone().serialize();
+
+        self.controls.lock(|controls| {
+            let mut new = controls.get();
+            let mirror = matches!(new[side as usize], Some(known) if known != data);
+
+            new[side as usize] = Some(data);
+
+            if mirror {
+                new[side.other() as usize] = Some(data);
             }
+
+            controls.set(new);
+
+            mirror
         })
     }
 }
+
+/// Embassy tasks cannot be generic, so one is generated per link.
+macro_rules! send_command_worker {
+    ($name:ident, $tx:ty, $side:expr) => {
+        #[embassy_executor::task]
+        pub async fn $name(
+            mut writer: HeadlightCommandWriter<$tx>,
+            ble: &'static BLE,
+            headlights: &'static Headlights,
+        ) {
+            let queue = headlights.queue($side);
+            let status = &headlights.statuses[$side as usize];
+
+            loop {
+                let bundle = fit($side, queue.recv().await);
+
+                if in_standby(ble, $side) {
+                    // only a change of state is worth waking the headlight for
+                    if !changes_state(&bundle) {
+                        if let ToHeadlightBundle::Request(request) = &bundle {
+                            let conns = ble.get_conns().await;
+                            answer_from_cache(ble.get_server(), &conns, $side, request);
+                        }
+
+                        continue;
+                    }
+
+                    // a headlight in standby loses whatever wakes it, so it is woken first
+                    status.reset();
+
+                    if let Err(e) = writer.wake().await {
+                        error!("Failed to wake {} headlight with error: {}", $side, e);
+                    }
+
+                    if with_timeout(WAKE_TIMEOUT, status.wait()).await.is_err() {
+                        warn!("{} headlight did not report waking from standby", $side);
+                    }
+                }
+
+                use_to_headlight_bundle!(bundle, |cmd| {
+                    if let Err(e) = writer.send(cmd).await {
+                        error!(
+                            "Command failed to send to {} headlight with error: {}",
+                            $side, e
+                        );
+                    }
+                })
+            }
+        }
+    };
+}
+
+send_command_worker!(send_left_worker, LeftTx, Side::Left);
+send_command_worker!(send_right_worker, RightTx, Side::Right);
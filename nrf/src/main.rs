@@ -14,18 +14,19 @@ use embassy_nrf::{
     config::Config as PeripheralConfig,
     interrupt,
     interrupt::InterruptExt,
-    peripherals::UARTE0,
+    peripherals::{UARTE0, UARTE1},
 };
 
 mod command;
 mod fmt;
 mod utils;
 
-use command::reader::receive_command_worker;
-use command::writer::{send_command_worker, WriterQueue};
+use command::reader::{receive_left_worker, receive_right_worker};
+use command::writer::{send_left_worker, send_right_worker, Headlights};
 use common::{
     assign_resources,
-    command::{reader::HeadlightCommandReader, writer::HeadlightCommandWriter},
+    command::{commands::Request, reader::HeadlightCommandReader, writer::HeadlightCommandWriter},
+    types::Side,
 };
 #[cfg(not(feature = "defmt"))]
 use cortex_m::peripheral::SCB;
@@ -33,9 +34,16 @@ use cortex_m::peripheral::SCB;
 use cortex_m_rt::exception;
 use embassy_nrf::peripherals;
 use utils::ble::BLE;
-use utils::uart::setup_uart;
+use utils::dfu::dfu_worker;
+use utils::identity::{identity_worker, init_identity};
+use utils::link::{link_monitor, link_monitor_worker};
+use utils::log::log_worker;
+use utils::schedule::{init_schedule, schedule_worker};
+use utils::security::{bond_storage_worker, passkey_display_worker, Bonder};
+use utils::storage::setup_flash;
+use utils::uart::{setup_left_uart, setup_right_uart};
 
-static SEND_QUEUE: WriterQueue = WriterQueue::new();
+static HEADLIGHTS: Headlights = Headlights::new();
 
 #[cfg(not(feature = "defmt"))]
 #[exception]
@@ -45,10 +53,11 @@ unsafe fn DefaultHandler(_irqn: i16) -> ! {
 
 bind_interrupts!(struct Irqs {
     UARTE0_UART0 => buffered_uarte::InterruptHandler<UARTE0>;
+    UARTE1 => buffered_uarte::InterruptHandler<UARTE1>;
 });
 
 assign_resources! {
-    pub serial: SerialResource {
+    pub serial_left: SerialLeftResource {
         uart: UARTE0,
         timer: TIMER1,
         ppi0: PPI_CH0,
@@ -56,6 +65,15 @@ assign_resources! {
         ppi_group: PPI_GROUP0,
         rx: P1_12,
         tx: P1_11
+    },
+    pub serial_right: SerialRightResource {
+        uart: UARTE1,
+        timer: TIMER2,
+        ppi0: PPI_CH2,
+        ppi1: PPI_CH3,
+        ppi_group: PPI_GROUP1,
+        rx: P1_14,
+        tx: P1_13
     }
 }
 
@@ -63,6 +81,7 @@ fn setup_interrupts(config: &mut PeripheralConfig) {
     config.gpiote_interrupt_priority = interrupt::Priority::P2;
     config.time_interrupt_priority = interrupt::Priority::P2;
     interrupt::UARTE0_UART0.set_priority(interrupt::Priority::P2);
+    interrupt::UARTE1.set_priority(interrupt::Priority::P2);
 }
 
 #[embassy_executor::main]
@@ -74,15 +93,45 @@ async fn main(spawner: Spawner) -> ! {
     let p = embassy_nrf::init(peripheral_config);
     let r = split_resources!(p);
 
-    let (rx, tx) = setup_uart(r.serial, Baudrate::BAUD9600);
+    let (left_rx, left_tx) = setup_left_uart(r.serial_left, Baudrate::BAUD9600);
+    let (right_rx, right_tx) = setup_right_uart(r.serial_right, Baudrate::BAUD9600);
 
-    let reader = HeadlightCommandReader::new(rx);
-    let writer = HeadlightCommandWriter::new(tx);
+    let left_reader = HeadlightCommandReader::with_observer(left_rx, link_monitor(Side::Left));
+    let right_reader = HeadlightCommandReader::with_observer(right_rx, link_monitor(Side::Right));
 
     let ble = BLE::init(&spawner).await;
+    let flash = setup_flash(ble.get_softdevice());
+    let bonder = Bonder::init(flash, ble.get_softdevice()).await;
+    init_identity(flash, ble).await;
+    init_schedule(flash, ble).await;
+
+    spawner.must_spawn(receive_left_worker(left_reader, ble, &HEADLIGHTS));
+    spawner.must_spawn(receive_right_worker(right_reader, ble, &HEADLIGHTS));
+    spawner.must_spawn(send_left_worker(
+        HeadlightCommandWriter::new(left_tx),
+        ble,
+        &HEADLIGHTS,
+    ));
+    spawner.must_spawn(send_right_worker(
+        HeadlightCommandWriter::new(right_tx),
+        ble,
+        &HEADLIGHTS,
+    ));
+    spawner.must_spawn(dfu_worker(flash, ble, &HEADLIGHTS));
+    spawner.must_spawn(bond_storage_worker(flash, bonder));
+    spawner.must_spawn(passkey_display_worker(ble, &HEADLIGHTS));
+    spawner.must_spawn(identity_worker(flash, ble));
+    spawner.must_spawn(schedule_worker(flash, ble, &HEADLIGHTS));
+    spawner.must_spawn(log_worker(ble));
+
+    for side in Side::BOTH {
+        spawner.must_spawn(link_monitor_worker(ble, &HEADLIGHTS, side));
+    }
 
-    spawner.must_spawn(receive_command_worker(reader, ble));
-    spawner.must_spawn(send_command_worker(writer, &SEND_QUEUE));
+    // the headlights report their properties on boot,
+    // but they may have booted before the relay (the config bounds the passkey flashes)
+    HEADLIGHTS.send_both(Request::Properties.into()).await;
+    HEADLIGHTS.send_both(Request::Config.into()).await;
 
-    ble.run(&SEND_QUEUE).await
+    ble.run(&spawner, &HEADLIGHTS, bonder).await
 }
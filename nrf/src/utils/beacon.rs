@@ -0,0 +1,105 @@
+use common::{
+    command::commands::{Beacon, BEACON_COMPANY_ID},
+    types::{HeadlightError, Mode},
+};
+use core::cell::{Cell, RefCell};
+use embassy_sync::{
+    blocking_mutex::{raw::ThreadModeRawMutex, Mutex},
+    signal::Signal,
+};
+use heapless::Vec;
+use tiny_serde::{prelude::*, Serialize};
+
+use crate::{fmt::unwrap, utils::identity::FullName};
+
+static BEACON: Mutex<ThreadModeRawMutex, Cell<Beacon>> = Mutex::new(Cell::new(Beacon {
+    mode: Mode::Idle,
+    latest_fault: HeadlightError::None,
+    temperature: 0,
+    target: 0,
+}));
+
+/// Signalled whenever the broadcast state changes and advertising must be restarted.
+pub static BEACON_CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Legacy advertising payloads are limited to 31 bytes.
+pub type AdvData = Vec<u8, 31>;
+
+const AD_FLAGS: u8 = 0x01;
+const AD_COMPLETE_128: u8 = 0x07;
+const AD_SHORTENED_NAME: u8 = 0x08;
+const AD_COMPLETE_NAME: u8 = 0x09;
+const AD_MANUFACTURER_DATA: u8 = 0xff;
+
+/// LE general discoverable, BR/EDR not supported
+const FLAGS: u8 = 0x06;
+
+/// Space left for the name after the flags and the beacon.
+const MAX_ADV_NAME_LEN: usize = 31 - 3 - (4 + <Beacon as _TinyDeSized>::SIZE) - 2;
+
+static NAME: Mutex<ThreadModeRawMutex, RefCell<FullName>> = Mutex::new(RefCell::new(Vec::new()));
+
+fn push_ad(data: &mut AdvData, ty: u8, value: &[u8]) {
+    unwrap!(data.push(value.len() as u8 + 1));
+    unwrap!(data.push(ty));
+    unwrap!(data.extend_from_slice(value));
+}
+
+pub fn update_beacon(f: impl FnOnce(&mut Beacon)) {
+    let changed = BEACON.lock(|beacon| {
+        let old = beacon.get();
+        let mut new = old;
+
+        f(&mut new);
+        beacon.set(new);
+
+        old.serialize() != new.serialize()
+    });
+
+    if changed {
+        BEACON_CHANGED.signal(());
+    }
+}
+
+pub fn set_adv_name(name: &[u8]) {
+    NAME.lock(|current| *current.borrow_mut() = unwrap!(Vec::from_slice(name)));
+    BEACON_CHANGED.signal(());
+}
+
+/// Flags, the current [`Beacon`] and the name, shortened if it does not fit.
+pub fn adv_data() -> AdvData {
+    let beacon = BEACON.lock(|beacon| beacon.get());
+    let mut manufacturer_data: Vec<u8, { 2 + <Beacon as _TinyDeSized>::SIZE }> = Vec::new();
+
+    unwrap!(manufacturer_data.extend_from_slice(&BEACON_COMPANY_ID.to_le_bytes()));
+    unwrap!(manufacturer_data.extend_from_slice(&beacon.serialize()));
+
+    let mut data = AdvData::new();
+
+    push_ad(&mut data, AD_FLAGS, &[FLAGS]);
+    push_ad(&mut data, AD_MANUFACTURER_DATA, &manufacturer_data);
+    NAME.lock(|name| {
+        let name = name.borrow();
+
+        if name.len() > MAX_ADV_NAME_LEN {
+            push_ad(&mut data, AD_SHORTENED_NAME, &name[..MAX_ADV_NAME_LEN]);
+        } else {
+            push_ad(&mut data, AD_COMPLETE_NAME, &name);
+        }
+    });
+
+    data
+}
+
+/// The headlight service UUID, which does not fit alongside the beacon.
+pub fn scan_data(service: &[u8; 16]) -> AdvData {
+    // UUIDs are little endian over the air
+    let mut uuid = *service;
+    uuid.reverse();
+
+    let mut data = AdvData::new();
+
+    push_ad(&mut data, AD_COMPLETE_128, &uuid);
+
+    data
+}
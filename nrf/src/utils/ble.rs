@@ -1,28 +1,69 @@
 use crate::{
-    command::writer::WriterQueue,
-    fmt::{error, info, unwrap},
+    command::writer::Headlights,
+    fmt::{error, info, unwrap, warn},
+    utils::{
+        beacon::{adv_data, scan_data, BEACON_CHANGED},
+        clock::sync_time,
+        dfu::{DfuRequest, DFU_QUEUE},
+        identity::{identity, update_identity, DEFAULT_NAME, MAX_FULL_NAME_LEN},
+        lease::ControlLease,
+        log::{self, replay},
+        power::PowerPolicy,
+        schedule::{observe, schedule, set_schedule},
+        security::Bonder,
+        storage::Name,
+    },
+};
+use common::{
+    command::commands::*,
+    types::{LogLevel, LogModule, RuleTrigger, Side},
+    utils::{bundles::ToHeadlightBundle, log::codes::time as time_codes},
 };
-use common::{command::commands::*, properties::PROPERTIES, utils::bundles::ToHeadlightBundle};
-use core::mem;
+use core::{future::pending, mem};
 use embassy_executor::Spawner;
-use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex};
+use embassy_futures::select::{select, select3, Either, Either3};
+use embassy_nrf::pac;
+use embassy_sync::{
+    blocking_mutex::raw::ThreadModeRawMutex, channel::TrySendError, mutex::Mutex, signal::Signal,
+};
+use embassy_time::Timer;
+use heapless::Vec;
 use nrf_softdevice::{
-    ble::{
-        advertisement_builder::{AdvertisementData, Complete128, CustomService, Flag, ShortName},
-        gatt_server, peripheral as ble_peripheral, Connection, Phy,
-    },
-    generate_adv_data, raw, Softdevice,
+    ble::{gatt_server, peripheral as ble_peripheral, Connection, Phy},
+    raw, Softdevice,
 };
 use static_cell::StaticCell;
 use tiny_serde::{prelude::*, Deserialize, Serialize};
-use uuid::uuid;
-
-const SCAN_DATA: &[u8] = generate_adv_data! {
-    full_name: "Headlights V2"
-};
+use uuid::{uuid, Uuid};
 
 static MODEL: StaticCell<BLE> = StaticCell::new();
 
+const HEADLIGHT_SERVICE: Uuid = uuid!("0b2adcf1-38a7-48f9-a61d-8311fe471b70");
+
+/// Notify the cached value of a characteristic when a client subscribes to it.
+macro_rules! notify_cached {
+    ($service:expr, $get:ident, $notify:ident, $conn:expr, $notifications:expr) => {
+        if $notifications {
+            if let Ok(data) = $service.$get() {
+                $service.$notify($conn, &data).ok();
+            }
+        }
+    };
+}
+
+/// Large enough for a [`DfuChunk`] to fit in a single write.
+const ATT_MTU: u16 = 247;
+
+/// Vendor specific UUID slots, at least one per custom service and characteristic.
+const VS_UUID_COUNT: u8 = 48;
+
+/// Bytes of the SoftDevice's attribute table, which holds every declaration, CCCD and value.
+///
+/// About 36 characteristics (25 of them notifying) with their values, the DFU chunk alone
+/// being 132 bytes, need roughly 3 KiB, well past the 1408 byte default.
+/// The SoftDevice's share of RAM (everything below the `RAM` origin in memory.x) covers it.
+const ATTR_TAB_SIZE: u32 = 6 * 1024;
+
 #[nrf_softdevice::gatt_service(uuid = "0b2adcf1-38a7-48f9-a61d-8311fe471b70")]
 pub struct HeadlightService {
     #[characteristic(uuid = "939f1423-2a0f-4a87-931f-5dae0b1ded7a", read)]
@@ -32,34 +73,309 @@ pub struct HeadlightService {
     pub request: [u8; <Request as _TinyDeSized>::SIZE],
 
     // endpoints
-    #[characteristic(uuid = "ccf82e46-5f1c-4671-b481-7ffd2854fed4", notify)]
+    #[characteristic(uuid = "ccf82e46-5f1c-4671-b481-7ffd2854fed4", read, notify)]
     pub status: [u8; <Status as _TinyDeSized>::SIZE],
 
-    #[characteristic(uuid = "eb483eeb-7b8e-45e0-910b-6c88fb3d75f3", write, notify)]
+    #[characteristic(
+        uuid = "eb483eeb-7b8e-45e0-910b-6c88fb3d75f3",
+        read,
+        write,
+        notify,
+        security = "lesc_mitm"
+    )]
     pub control: [u8; <Control as _TinyDeSized>::SIZE],
 
-    #[characteristic(uuid = "30f62c01-d9d8-4c14-9a66-36ad0d92edbf", notify)]
+    #[characteristic(uuid = "30f62c01-d9d8-4c14-9a66-36ad0d92edbf", read, notify)]
     pub monitor: [u8; <Monitor as _TinyDeSized>::SIZE],
 
-    #[characteristic(uuid = "73e4b52c-4ae2-4901-b78b-8f95f3a60cdb", write, notify)]
+    #[characteristic(
+        uuid = "73e4b52c-4ae2-4901-b78b-8f95f3a60cdb",
+        read,
+        write,
+        notify,
+        security = "lesc_mitm"
+    )]
     pub config: [u8; <Config as _TinyDeSized>::SIZE],
 
-    #[characteristic(uuid = "a7e05ec9-ed47-49fe-8b5b-4d030c687032", write)]
+    #[characteristic(uuid = "764349d4-8f45-4671-9dec-2f8c61ee4696", read, notify)]
+    pub derating: [u8; <Derating as _TinyDeSized>::SIZE],
+
+    /// Button presses and switch changes on either headlight
+    #[characteristic(uuid = "df6cc1b2-d9a8-4312-8562-6ad8ffd26bc8", notify)]
+    pub input_event: [u8; <InputEvent as _TinyDeSized>::SIZE],
+
+    /// Diagnostic entries from the relay and both headlights, the buffered ones are sent on subscribing
+    #[characteristic(uuid = "a6bb2c0d-f25b-4582-9c1e-3cd2ec62f40b", notify)]
+    pub log: [u8; <Log as _TinyDeSized>::SIZE],
+
+    /// Turn signal, brake and ignition changes seen by either headlight
+    #[characteristic(uuid = "fc8eafc9-7714-4699-98c9-920d17743c3e", notify)]
+    pub signal_event: [u8; <SignalEvent as _TinyDeSized>::SIZE],
+
+    /// Day/night changes seen by either headlight's light sensor
+    #[characteristic(uuid = "167efb1a-bb7c-4b2d-8267-d30eb85dd255", notify)]
+    pub ambient_event: [u8; <AmbientEvent as _TinyDeSized>::SIZE],
+
+    #[characteristic(
+        uuid = "a7e05ec9-ed47-49fe-8b5b-4d030c687032",
+        write,
+        security = "lesc_mitm"
+    )]
     pub reset: [u8; <Reset as _TinyDeSized>::SIZE],
 
+    #[characteristic(
+        uuid = "5d1c6f0e-2b8a-4f3e-9c47-b1e2d6a9f358",
+        write,
+        security = "lesc_mitm"
+    )]
+    pub clear_fault: [u8; <ClearFault as _TinyDeSized>::SIZE],
+
+    /// Written by the app on connecting, the relay forwards it to the headlights
+    #[characteristic(
+        uuid = "03fd9c29-f550-44c4-8a56-f72f8bbd9f33",
+        write,
+        security = "lesc_mitm"
+    )]
+    pub time: [u8; <TimeSync as _TinyDeSized>::SIZE],
+
+    // identity
+    #[characteristic(
+        uuid = "d8d4aa33-7e8c-4ff1-a6ab-c0020d0e92f7",
+        read,
+        write,
+        security = "lesc_mitm"
+    )]
+    pub name: Name,
+
+    #[characteristic(
+        uuid = "08d961c2-5256-4cfe-94fa-e7fe615e87c5",
+        read,
+        write,
+        security = "lesc_mitm"
+    )]
+    pub name_suffix: bool,
+
+    // schedule
+    /// Every rule the relay executes by itself, see [`Schedule`]
+    #[characteristic(
+        uuid = "f3ede5b7-9356-4c43-9707-7d8b824fba5e",
+        read,
+        write,
+        security = "lesc_mitm"
+    )]
+    pub schedule: [u8; SCHEDULE_SIZE],
+
     // diagnostic
     #[characteristic(uuid = "a16bc310-eb50-414e-87b3-2199e79523c2", notify)]
     pub app_error: [u8; <AppError as _TinyDeSized>::SIZE],
 }
 
+/// State of the left headlight alone.
+///
+/// Control written here is only sent to this side,
+/// whereas the combined [`HeadlightService`] commands both sides in lockstep.
+#[nrf_softdevice::gatt_service(uuid = "4a868ee3-ab5a-4e9f-a781-df8dcf073d61")]
+pub struct LeftHeadlightService {
+    #[characteristic(uuid = "75e62aff-36eb-432e-84e2-fb9b3d145f12", read, notify)]
+    pub status: [u8; <Status as _TinyDeSized>::SIZE],
+
+    #[characteristic(
+        uuid = "26db6d5a-a053-4ce1-891c-7e0c736f024e",
+        read,
+        write,
+        notify,
+        security = "lesc_mitm"
+    )]
+    pub control: [u8; <Control as _TinyDeSized>::SIZE],
+
+    #[characteristic(uuid = "a9f871e6-f025-4dc6-9d59-606c155df86a", read, notify)]
+    pub monitor: [u8; <Monitor as _TinyDeSized>::SIZE],
+
+    #[characteristic(uuid = "be1ba50e-b62b-4363-9b35-d66b6365c4c0", read, notify)]
+    pub link_status: [u8; <LinkStatus as _TinyDeSized>::SIZE],
+}
+
+/// State of the right headlight alone, identical to [`LeftHeadlightService`].
+#[nrf_softdevice::gatt_service(uuid = "e6f72dfb-9b1e-4a48-9a17-f9cfd996e32a")]
+pub struct RightHeadlightService {
+    #[characteristic(uuid = "a6679784-6820-42bf-be17-75cb72b0990f", read, notify)]
+    pub status: [u8; <Status as _TinyDeSized>::SIZE],
+
+    #[characteristic(
+        uuid = "4c320670-ef90-4340-b1f6-ee8e0c13dfaf",
+        read,
+        write,
+        notify,
+        security = "lesc_mitm"
+    )]
+    pub control: [u8; <Control as _TinyDeSized>::SIZE],
+
+    #[characteristic(uuid = "51afa025-d8be-49fe-aa68-25e5fc04c678", read, notify)]
+    pub monitor: [u8; <Monitor as _TinyDeSized>::SIZE],
+
+    #[characteristic(uuid = "322a3316-5d2a-410f-b9e6-b1c79bccd754", read, notify)]
+    pub link_status: [u8; <LinkStatus as _TinyDeSized>::SIZE],
+}
+
+/// Evaluate `$body` with `$service` bound to the service of the headlight on `$side`.
+macro_rules! side_service {
+    ($server:expr, $side:expr, |$service:ident| $body:expr) => {
+        match $side {
+            ::common::types::Side::Left => {
+                let $service = &$server.left;
+                $body
+            }
+            ::common::types::Side::Right => {
+                let $service = &$server.right;
+                $body
+            }
+        }
+    };
+}
+
+pub(crate) use side_service;
+
+/// Events of either per-side service.
+enum SideEvent {
+    ControlWrite([u8; <Control as _TinyDeSized>::SIZE]),
+    StatusCccdWrite { notifications: bool },
+    ControlCccdWrite { notifications: bool },
+    MonitorCccdWrite { notifications: bool },
+    LinkStatusCccdWrite { notifications: bool },
+}
+
+macro_rules! impl_from_side_event {
+    ($event:ident) => {
+        impl From<$event> for SideEvent {
+            fn from(e: $event) -> Self {
+                match e {
+                    $event::ControlWrite(data) => Self::ControlWrite(data),
+                    $event::StatusCccdWrite { notifications } => {
+                        Self::StatusCccdWrite { notifications }
+                    }
+                    $event::ControlCccdWrite { notifications } => {
+                        Self::ControlCccdWrite { notifications }
+                    }
+                    $event::MonitorCccdWrite { notifications } => {
+                        Self::MonitorCccdWrite { notifications }
+                    }
+                    $event::LinkStatusCccdWrite { notifications } => {
+                        Self::LinkStatusCccdWrite { notifications }
+                    }
+                }
+            }
+        }
+    };
+}
+
+impl_from_side_event!(LeftHeadlightServiceEvent);
+impl_from_side_event!(RightHeadlightServiceEvent);
+
+/// Over-the-air update of the headlight firmware, buffered by the relay.
+#[nrf_softdevice::gatt_service(uuid = "f62f7cb5-d8ae-4269-8899-e3eb95be54ba")]
+pub struct DfuService {
+    #[characteristic(
+        uuid = "a4e48da7-fd7e-4731-9b6b-9e804e7e88c8",
+        write,
+        security = "lesc_mitm"
+    )]
+    pub begin: [u8; <FirmwareBegin as _TinyDeSized>::SIZE],
+
+    #[characteristic(
+        uuid = "ed318495-4427-4ac8-bd71-0a3729867f82",
+        write,
+        security = "lesc_mitm"
+    )]
+    pub chunk: [u8; <DfuChunk as _TinyDeSized>::SIZE],
+
+    #[characteristic(
+        uuid = "7dd60670-4858-44c0-a16e-c121e8b9d305",
+        write,
+        security = "lesc_mitm"
+    )]
+    pub commit: [u8; <FirmwareCommit as _TinyDeSized>::SIZE],
+
+    #[characteristic(uuid = "6b81f6b8-57c4-46af-9978-e771499df4f0", read, notify)]
+    pub progress: [u8; <DfuProgress as _TinyDeSized>::SIZE],
+}
+
+/// Standard Device Information Service, so generic tools can identify the relay.
+#[nrf_softdevice::gatt_service(uuid = "180a")]
+pub struct DeviceInformationService {
+    #[characteristic(uuid = "2a29", read)]
+    pub manufacturer_name: GattString,
+
+    #[characteristic(uuid = "2a24", read)]
+    pub model_number: GattString,
+
+    #[characteristic(uuid = "2a25", read)]
+    pub serial_number: GattString,
+
+    /// Filled in once the headlight reports its properties
+    #[characteristic(uuid = "2a27", read)]
+    pub hardware_revision: GattString,
+
+    /// Filled in once the headlight reports its properties
+    #[characteristic(uuid = "2a26", read)]
+    pub firmware_revision: GattString,
+}
+
+/// Standard Environmental Sensing Service, exposing the headlight's FET temperature.
+///
+/// The headlight has no supply voltage measurement, so only temperature is exposed.
+#[nrf_softdevice::gatt_service(uuid = "181a")]
+pub struct EnvironmentalSensingService {
+    /// Units of 0.01 degrees Celsius
+    #[characteristic(uuid = "2a6e", read, notify)]
+    pub temperature: i16,
+}
+
 #[nrf_softdevice::gatt_server]
 pub struct Server {
     pub headlight: HeadlightService,
+    pub left: LeftHeadlightService,
+    pub right: RightHeadlightService,
+    pub dfu: DfuService,
+    pub device_information: DeviceInformationService,
+    pub environmental_sensing: EnvironmentalSensingService,
+}
+
+pub type GattString = Vec<u8, 16>;
+
+/// Strings longer than a [`GattString`] are truncated.
+pub fn gatt_string(s: &str) -> GattString {
+    let bytes = s.as_bytes();
+
+    unwrap!(Vec::from_slice(&bytes[..bytes.len().min(16)]))
 }
 
+/// Factory-unique device ID, as 16 hex digits.
+fn serial_number() -> GattString {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    let ficr = unsafe { &*pac::FICR::ptr() };
+    let id = (ficr.deviceid[1].read().bits() as u64) << 32 | ficr.deviceid[0].read().bits() as u64;
+    let mut serial = GattString::new();
+
+    for i in (0..16).rev() {
+        serial.push(HEX[(id >> (i * 4)) as usize & 0xf]).ok();
+    }
+
+    serial
+}
+
+pub const MAX_CONNECTIONS: usize = 3;
+
+pub type Connections = Vec<Connection, MAX_CONNECTIONS>;
+
+/// Signalled whenever a connection ends, so advertising can resume if it had stopped.
+static CONN_RELEASED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
 pub struct BLE {
     sd: &'static Softdevice,
-    conn: Mutex<ThreadModeRawMutex, Option<Connection>>,
+    conns: Mutex<ThreadModeRawMutex, Connections>,
+    lease: ControlLease,
+    power: PowerPolicy,
     server: Server,
 }
 
@@ -67,7 +383,9 @@ impl BLE {
     const fn new(sd: &'static Softdevice, server: Server) -> Self {
         Self {
             sd,
-            conn: Mutex::new(None),
+            conns: Mutex::new(Vec::new()),
+            lease: ControlLease::new(),
+            power: PowerPolicy::new(),
             server,
         }
     }
@@ -75,140 +393,488 @@ impl BLE {
     pub async fn init(spawner: &Spawner) -> &'static Self {
         let sd_config = nrf_softdevice::Config {
             conn_gap: Some(raw::ble_gap_conn_cfg_t {
-                conn_count: raw::BLE_GAP_CONN_COUNT_DEFAULT as u8,
+                conn_count: MAX_CONNECTIONS as u8,
                 event_length: raw::BLE_GAP_EVENT_LENGTH_DEFAULT as u16,
             }),
+            gap_role_count: Some(raw::ble_gap_cfg_role_count_t {
+                adv_set_count: raw::BLE_GAP_ADV_SET_COUNT_DEFAULT as u8,
+                periph_role_count: MAX_CONNECTIONS as u8,
+                central_role_count: 0,
+                central_sec_count: 0,
+                _bitfield_1: raw::ble_gap_cfg_role_count_t::new_bitfield_1(0),
+            }),
             gap_device_name: Some(raw::ble_gap_cfg_device_name_t {
-                p_value: b"Headlights V2" as *const u8 as _,
-                current_len: 13,
-                max_len: 13,
+                p_value: DEFAULT_NAME.as_ptr() as _,
+                current_len: DEFAULT_NAME.len() as u16,
+                max_len: MAX_FULL_NAME_LEN as u16,
                 write_perm: unsafe { mem::zeroed() },
                 _bitfield_1: raw::ble_gap_cfg_device_name_t::new_bitfield_1(
                     raw::BLE_GATTS_VLOC_STACK as u8,
                 ),
             }),
-            conn_gatt: Some(raw::ble_gatt_conn_cfg_t {
-                att_mtu: raw::BLE_GATT_ATT_MTU_DEFAULT as u16,
+            conn_gatt: Some(raw::ble_gatt_conn_cfg_t { att_mtu: ATT_MTU }),
+            gatts_attr_tab_size: Some(raw::ble_gatts_cfg_attr_tab_size_t {
+                attr_tab_size: ATTR_TAB_SIZE,
             }),
+            // every random 128-bit UUID has its own base, so each one occupies a slot
             common_vs_uuid: Some(raw::ble_common_cfg_vs_uuid_t {
-                vs_uuid_count: raw::BLE_UUID_VS_COUNT_DEFAULT as u8,
+                vs_uuid_count: VS_UUID_COUNT,
             }),
             ..Default::default()
         };
 
         let sd = Softdevice::enable(&sd_config);
 
-        let mut server = unwrap!(Server::new(sd));
+        let server = unwrap!(Server::new(sd));
 
-        Self::set_version(&mut server);
+        let info = &server.device_information;
+        unwrap!(info.manufacturer_name_set(&gatt_string("AdinAck")));
+        unwrap!(info.model_number_set(&gatt_string("Headlights V2")));
+        unwrap!(info.serial_number_set(&serial_number()));
 
         spawner.must_spawn(softdevice_task(sd));
 
         MODEL.init(BLE::new(sd, server))
     }
 
-    fn set_version(server: &mut Server) {
-        match server.headlight.properties_set(&PROPERTIES.serialize()) {
-            Ok(_) => {}
-            Err(e) => {
-                // i can't think of anything that would cause this
-                error!("Failed to set properties with error: {}.", e);
-            }
-        }
+    pub async fn get_conns(&self) -> Connections {
+        let lock = self.conns.lock().await;
+        lock.clone()
     }
 
-    pub async fn get_conn(&self) -> Option<Connection> {
-        let lock = self.conn.lock().await;
-        lock.clone()
+    async fn add_conn(&self, conn: Connection) {
+        let mut lock = self.conns.lock().await;
+        // advertising only happens while there is room
+        lock.push(conn).ok();
+    }
+
+    async fn remove_disconnected(&self) {
+        let mut lock = self.conns.lock().await;
+        lock.retain(|conn| conn.handle().is_some());
     }
 
-    pub async fn set_conn(&self, conn: Option<Connection>) {
-        let mut lock = self.conn.lock().await;
-        *lock = conn;
+    pub fn get_softdevice(&self) -> &'static Softdevice {
+        self.sd
     }
 
     pub fn get_server(&self) -> &Server {
         &self.server
     }
 
-    pub async fn run(&self, queue: &'static WriterQueue) -> ! {
-        let adv_config = ble_peripheral::Config {
-            primary_phy: Phy::M1,
-            secondary_phy: Phy::M1,
-            ..Default::default()
-        };
+    /// Advertise until a central connects,
+    /// restarting whenever the beacon changes or advertising slows down.
+    async fn advertise(
+        &self,
+        config: &ble_peripheral::Config,
+        bonder: &'static Bonder,
+    ) -> Connection {
+        let scan_data = scan_data(HEADLIGHT_SERVICE.as_bytes());
 
-        // let adv_data = generate_adv_data! {
-        //     flags: (LE_Only, GeneralDiscovery),
-        //     services: Complete128(Custom("0b2adcf1-38a7-48f9-a61d-8311fe471b70")),
-        //     short_name: "HLV2"
-        // };
-
-        let headlight_service = CustomService(
-            uuid!("0b2adcf1-38a7-48f9-a61d-8311fe471b70")
-                .as_bytes()
-                .clone(),
-        );
-
-        let adv_data = AdvertisementData::new()
-            .flags([Flag::LE_Only, Flag::GeneralDiscovery])
-            .services(Complete128([headlight_service]))
-            .name(ShortName("HLV2"));
-
-        let adv = ble_peripheral::ConnectableAdvertisement::ScannableUndirected {
-            adv_data: adv_data.as_slice(),
-            scan_data: SCAN_DATA,
-        };
+        loop {
+            let adv_data = adv_data();
+            let adv = ble_peripheral::ConnectableAdvertisement::ScannableUndirected {
+                adv_data: &adv_data,
+                scan_data: &scan_data,
+            };
+
+            let (interval, slowdown) = self.power.advertising_interval();
+            let config = ble_peripheral::Config {
+                interval,
+                ..*config
+            };
+
+            // writes that change the headlight's behavior require an authenticated (passkey) link
+            match select3(
+                ble_peripheral::advertise_pairable(self.sd, adv, &config, bonder),
+                BEACON_CHANGED.wait(),
+                async {
+                    match slowdown {
+                        Some(slowdown) => Timer::at(slowdown).await,
+                        None => pending().await,
+                    }
+                },
+            )
+            .await
+            {
+                Either3::First(conn) => break unwrap!(conn),
+                Either3::Second(_) | Either3::Third(_) => continue,
+            }
+        }
+    }
+
+    /// Keep broadcasting the beacon (non-connectable) while no more centrals can connect.
+    async fn broadcast(&self, config: &ble_peripheral::Config) -> ! {
+        let scan_data = scan_data(HEADLIGHT_SERVICE.as_bytes());
 
         loop {
-            let conn =
-                unwrap!(ble_peripheral::advertise_connectable(self.sd, adv, &adv_config).await);
+            let adv_data = adv_data();
+            let adv = ble_peripheral::NonconnectableAdvertisement::ScannableUndirected {
+                adv_data: &adv_data,
+                scan_data: &scan_data,
+            };
+
+            // centrals are connected, so there is no point in hurrying
+            let config = ble_peripheral::Config {
+                interval: self.power.advertising_interval().0,
+                ..*config
+            };
+
+            match select(
+                ble_peripheral::advertise(self.sd, adv, &config),
+                BEACON_CHANGED.wait(),
+            )
+            .await
+            {
+                Either::First(Err(e)) => {
+                    error!("Broadcasting failed with error: {:?}", e);
+                    BEACON_CHANGED.wait().await;
+                }
+                _ => continue,
+            }
+        }
+    }
 
-            self.set_conn(Some(conn.clone())).await;
+    /// Control may only be written by the connection holding the lease,
+    /// the rejected connection is notified.
+    fn acquire_lease(&self, conn: &Connection) -> bool {
+        if conn
+            .handle()
+            .map_or(false, |handle| self.lease.acquire(handle))
+        {
+            return true;
+        }
 
-            info!("advertising done!");
+        warn!("Control write rejected, another connection holds the lease.");
+        self.server
+            .headlight
+            .app_error_notify(conn, &AppError::ControlLeased.serialize())
+            .ok();
+
+        false
+    }
+
+    /// Hand a command written by `conn` to `send`, notifying the connection if it was invalid or could not be queued.
+    fn enqueue(
+        &self,
+        conn: &Connection,
+        bundle: Option<ToHeadlightBundle>,
+        send: impl FnOnce(ToHeadlightBundle) -> Result<(), TrySendError<ToHeadlightBundle>>,
+    ) {
+        if let Some(bundle) = bundle {
+            // any command from the app counts as activity, except requests (apps poll state)
+            if !matches!(bundle, ToHeadlightBundle::Request(_)) {
+                observe(RuleTrigger::Idle);
+            }
+
+            if send(bundle).is_err() {
+                // only possible error is it's full
+                error!("Command ingestion channel overflowed (commands are being received faster than they can be dispatched).");
+                self.server
+                    .headlight
+                    .app_error_notify(conn, &AppError::TooFast.serialize())
+                    .ok();
+            }
+        } else {
+            error!("Invalid BLE packet received (command could not be serialized from received bytes).");
+            self.server
+                .headlight
+                .app_error_notify(conn, &AppError::InvalidPacket.serialize())
+                .ok();
+        }
+    }
 
-            let e = gatt_server::run(&conn, &self.server, |e| match e {
-                ServerEvent::Headlight(e) => {
-                    let bundle: Option<ToHeadlightBundle> = match e {
-                        HeadlightServiceEvent::RequestWrite(data) => {
-                            Request::deserialize(data).map(Request::into)
+    fn on_side_event(&self, conn: &Connection, headlights: &Headlights, side: Side, e: SideEvent) {
+        match e {
+            SideEvent::ControlWrite(data) => {
+                if self.acquire_lease(conn) {
+                    self.enqueue(
+                        conn,
+                        Control::deserialize(data).map(Control::into),
+                        |bundle| headlights.try_send(side, bundle),
+                    );
+                }
+            }
+            SideEvent::StatusCccdWrite { notifications } => {
+                side_service!(self.server, side, |service| notify_cached!(
+                    service,
+                    status_get,
+                    status_notify,
+                    conn,
+                    notifications
+                ));
+            }
+            SideEvent::ControlCccdWrite { notifications } => {
+                side_service!(self.server, side, |service| notify_cached!(
+                    service,
+                    control_get,
+                    control_notify,
+                    conn,
+                    notifications
+                ));
+            }
+            SideEvent::MonitorCccdWrite { notifications } => {
+                side_service!(self.server, side, |service| notify_cached!(
+                    service,
+                    monitor_get,
+                    monitor_notify,
+                    conn,
+                    notifications
+                ));
+            }
+            SideEvent::LinkStatusCccdWrite { notifications } => {
+                side_service!(self.server, side, |service| notify_cached!(
+                    service,
+                    link_status_get,
+                    link_status_notify,
+                    conn,
+                    notifications
+                ));
+            }
+        }
+    }
+
+    fn on_event(&self, conn: &Connection, headlights: &Headlights, e: ServerEvent) {
+        match e {
+            ServerEvent::Headlight(e) => {
+                let bundle: Option<ToHeadlightBundle> = match e {
+                    HeadlightServiceEvent::RequestWrite(data) => {
+                        Request::deserialize(data).map(Request::into)
+                    }
+                    HeadlightServiceEvent::ControlWrite(data) => {
+                        if !self.acquire_lease(conn) {
+                            return;
                         }
-                        HeadlightServiceEvent::ControlWrite(data) => {
-                            Control::deserialize(data).map(Control::into)
+
+                        Control::deserialize(data).map(Control::into)
+                    }
+                    HeadlightServiceEvent::ConfigWrite(data) => {
+                        Config::deserialize(data).map(Config::into)
+                    }
+                    HeadlightServiceEvent::ResetWrite(data) => {
+                        Reset::deserialize(data).map(Reset::into)
+                    }
+                    HeadlightServiceEvent::ClearFaultWrite(data) => {
+                        ClearFault::deserialize(data).map(ClearFault::into)
+                    }
+                    HeadlightServiceEvent::TimeWrite(data) => {
+                        let time = TimeSync::deserialize(data);
+
+                        if let Some(time) = &time {
+                            sync_time(time);
+                            log::record(
+                                LogLevel::Info,
+                                LogModule::Time,
+                                time_codes::SYNCED,
+                                time.unix,
+                                0,
+                            );
                         }
-                        HeadlightServiceEvent::ConfigWrite(data) => {
-                            Config::deserialize(data).map(Config::into)
+
+                        time.map(TimeSync::into)
+                    }
+                    HeadlightServiceEvent::NameWrite(name) => {
+                        if name.is_empty() || core::str::from_utf8(&name).is_err() {
+                            error!("Invalid name received (must be non-empty UTF-8).");
+                            self.server.headlight.name_set(&identity().name).ok();
+                            self.server
+                                .headlight
+                                .app_error_notify(conn, &AppError::InvalidPacket.serialize())
+                                .ok();
+                        } else {
+                            update_identity(|identity| identity.name = name);
                         }
-                        HeadlightServiceEvent::ResetWrite(data) => {
-                            Reset::deserialize(data).map(Reset::into)
+
+                        return;
+                    }
+                    HeadlightServiceEvent::NameSuffixWrite(suffix) => {
+                        update_identity(|identity| identity.suffix = suffix);
+                        return;
+                    }
+                    HeadlightServiceEvent::ScheduleWrite(data) => {
+                        if let Some(new) = deserialize_schedule(data) {
+                            set_schedule(new);
+                        } else {
+                            error!("Invalid schedule received.");
+                            self.server
+                                .headlight
+                                .schedule_set(&serialize_schedule(&schedule()))
+                                .ok();
+                            self.server
+                                .headlight
+                                .app_error_notify(conn, &AppError::InvalidPacket.serialize())
+                                .ok();
                         }
-                        _ => return
-                    };
 
-                    if let Some(bundle) = bundle {
-                        if queue.try_send(bundle).is_err() { // only possible error is it's full
-                            error!("Command ingestion channel overflowed (commands are being received faster than they can be dispatched).");
-                            self.server.headlight.app_error_notify(&conn, &AppError::TooFast.serialize()).ok();
+                        return;
+                    }
+                    HeadlightServiceEvent::LogCccdWrite { notifications } => {
+                        if notifications {
+                            replay(self, conn);
                         }
-                    } else {
-                        error!("Invalid BLE packet received (command could not be serialized from received bytes).");
+
+                        return;
+                    }
+                    HeadlightServiceEvent::StatusCccdWrite { notifications } => {
+                        notify_cached!(
+                            self.server.headlight,
+                            status_get,
+                            status_notify,
+                            conn,
+                            notifications
+                        );
+                        return;
+                    }
+                    HeadlightServiceEvent::ControlCccdWrite { notifications } => {
+                        notify_cached!(
+                            self.server.headlight,
+                            control_get,
+                            control_notify,
+                            conn,
+                            notifications
+                        );
+                        return;
+                    }
+                    HeadlightServiceEvent::MonitorCccdWrite { notifications } => {
+                        notify_cached!(
+                            self.server.headlight,
+                            monitor_get,
+                            monitor_notify,
+                            conn,
+                            notifications
+                        );
+                        return;
+                    }
+                    HeadlightServiceEvent::ConfigCccdWrite { notifications } => {
+                        notify_cached!(
+                            self.server.headlight,
+                            config_get,
+                            config_notify,
+                            conn,
+                            notifications
+                        );
+                        return;
+                    }
+                    HeadlightServiceEvent::DeratingCccdWrite { notifications } => {
+                        notify_cached!(
+                            self.server.headlight,
+                            derating_get,
+                            derating_notify,
+                            conn,
+                            notifications
+                        );
+                        return;
+                    }
+                    _ => return,
+                };
+
+                // commands written to the combined service apply to both sides
+                self.enqueue(conn, bundle, |bundle| headlights.try_send_both(bundle));
+            }
+            ServerEvent::Left(e) => self.on_side_event(conn, headlights, Side::Left, e.into()),
+            ServerEvent::Right(e) => self.on_side_event(conn, headlights, Side::Right, e.into()),
+            ServerEvent::Dfu(e) => {
+                let request = match e {
+                    DfuServiceEvent::BeginWrite(data) => {
+                        FirmwareBegin::deserialize(data).map(DfuRequest::Begin)
+                    }
+                    DfuServiceEvent::ChunkWrite(data) => {
+                        DfuChunk::deserialize(data).map(DfuRequest::Chunk)
+                    }
+                    DfuServiceEvent::CommitWrite(data) => {
+                        FirmwareCommit::deserialize(data).map(DfuRequest::Commit)
+                    }
+                    _ => return,
+                };
+
+                if let Some(request) = request {
+                    // the app waits for progress after every write, so this only fills if it misbehaves
+                    if DFU_QUEUE.try_send(request).is_err() {
+                        error!("DFU channel overflowed (chunks are being received faster than they can be written).");
                         self.server
                             .headlight
-                            .app_error_notify(&conn, &AppError::InvalidPacket.serialize())
+                            .app_error_notify(conn, &AppError::TooFast.serialize())
                             .ok();
                     }
+                } else {
+                    error!("Invalid BLE packet received (command could not be serialized from received bytes).");
+                    self.server
+                        .headlight
+                        .app_error_notify(conn, &AppError::InvalidPacket.serialize())
+                        .ok();
                 }
-            })
-            .await;
+            }
+            ServerEvent::EnvironmentalSensing(e) => match e {
+                EnvironmentalSensingServiceEvent::TemperatureCccdWrite { notifications } => {
+                    notify_cached!(
+                        self.server.environmental_sensing,
+                        temperature_get,
+                        temperature_notify,
+                        conn,
+                        notifications
+                    );
+                }
+            },
+            // read only
+            ServerEvent::DeviceInformation(_) => {}
+        }
+    }
+
+    pub async fn run(
+        &'static self,
+        spawner: &Spawner,
+        headlights: &'static Headlights,
+        bonder: &'static Bonder,
+    ) -> ! {
+        let adv_config = ble_peripheral::Config {
+            primary_phy: Phy::M1,
+            secondary_phy: Phy::M1,
+            ..Default::default()
+        };
 
-            self.set_conn(None).await;
+        loop {
+            CONN_RELEASED.reset();
+
+            if self.conns.lock().await.is_full() {
+                // no more centrals can connect, so only the beacon is broadcast
+                select(self.broadcast(&adv_config), CONN_RELEASED.wait()).await;
+                continue;
+            }
+
+            let conn = self.advertise(&adv_config, bonder).await;
+
+            info!("advertising done!");
 
-            info!("gatt_server run exited with error: {:?}", e);
+            self.power.activity();
+            self.add_conn(conn.clone()).await;
+
+            // refresh the cached state, it is notified as soon as the client subscribes
+            for request in [Request::Status, Request::Control, Request::Config] {
+                headlights.send_both(request.into()).await;
+            }
+
+            spawner.must_spawn(gatt_task(self, conn, headlights));
         }
     }
 }
 
+#[embassy_executor::task(pool_size = MAX_CONNECTIONS)]
+async fn gatt_task(ble: &'static BLE, conn: Connection, headlights: &'static Headlights) {
+    // the handle is no longer available once disconnected
+    let handle = conn.handle();
+
+    let e = gatt_server::run(&conn, &ble.server, |e| ble.on_event(&conn, headlights, e)).await;
+
+    info!("gatt_server run exited with error: {:?}", e);
+
+    if let Some(handle) = handle {
+        ble.lease.release(handle);
+    }
+
+    ble.remove_disconnected().await;
+    ble.power.activity();
+    CONN_RELEASED.signal(());
+}
+
 #[embassy_executor::task]
 async fn softdevice_task(sd: &'static Softdevice) -> ! {
     sd.run().await
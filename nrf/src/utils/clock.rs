@@ -0,0 +1,28 @@
+use common::{command::commands::TimeSync, utils::clock::WallClock};
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{raw::ThreadModeRawMutex, Mutex};
+use embassy_time::Instant;
+
+/// Wall-clock time synced by the app, lost whenever the relay resets.
+static CLOCK: Mutex<ThreadModeRawMutex, RefCell<WallClock>> =
+    Mutex::new(RefCell::new(WallClock::new()));
+
+pub fn sync_time(time: &TimeSync) {
+    let uptime = Instant::now().as_millis();
+
+    CLOCK.lock(|clock| clock.borrow_mut().sync(time, uptime));
+}
+
+/// The current time, if the app has synced it since boot.
+pub fn now() -> Option<TimeSync> {
+    let uptime = Instant::now().as_millis();
+
+    CLOCK.lock(|clock| clock.borrow().now(uptime))
+}
+
+/// The current Unix time in seconds, or 0 if the app has not synced it since boot.
+pub fn timestamp() -> u32 {
+    let uptime = Instant::now().as_millis();
+
+    CLOCK.lock(|clock| clock.borrow().timestamp(uptime))
+}
@@ -0,0 +1,344 @@
+use common::{
+    command::commands::*,
+    types::{DfuStage, FirmwareError, LogLevel, LogModule, Side},
+    utils::{bundles::ToHeadlightBundle, log::codes::update},
+    IMAGE_CRC,
+};
+use core::cmp::min;
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, channel::Channel, signal::Signal};
+use embassy_time::{with_timeout, Duration};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use nrf_softdevice::ble::Connection;
+use tiny_serde::Serialize;
+
+use crate::{
+    command::writer::{Headlights, WriterQueue},
+    fmt::{info, warn},
+    utils::{
+        ble::{Server, BLE},
+        log,
+        storage::SharedFlash,
+    },
+};
+
+/// Acknowledgements from each headlight for each step of an update.
+static FIRMWARE_STATUS: [Signal<ThreadModeRawMutex, FirmwareStatus>; 2] =
+    [Signal::new(), Signal::new()];
+
+pub fn firmware_status(side: Side) -> &'static Signal<ThreadModeRawMutex, FirmwareStatus> {
+    &FIRMWARE_STATUS[side as usize]
+}
+
+/// Requests from the app, written to the DFU service.
+pub enum DfuRequest {
+    Begin(FirmwareBegin),
+    Chunk(DfuChunk),
+    Commit(FirmwareCommit),
+}
+
+pub type DfuQueue = Channel<ThreadModeRawMutex, DfuRequest, 2>;
+
+pub static DFU_QUEUE: DfuQueue = DfuQueue::new();
+
+/// Must match the `STAGING` region in `memory.x`.
+const STAGING_START: u32 = 0x000f_d000;
+const STAGING_SIZE: u32 = 12 * 1024;
+
+/// Erasing the DFU partition takes considerably longer than writing a chunk.
+const BEGIN_TIMEOUT: Duration = Duration::from_secs(5);
+const CHUNK_TIMEOUT: Duration = Duration::from_millis(500);
+
+async fn send_and_wait(
+    queue: &WriterQueue,
+    acknowledgement: &Signal<ThreadModeRawMutex, FirmwareStatus>,
+    cmd: impl Into<ToHeadlightBundle>,
+    timeout: Duration,
+) -> Result<FirmwareStatus, FirmwareError> {
+    acknowledgement.reset();
+    queue.send(cmd.into()).await;
+
+    let status = with_timeout(timeout, acknowledgement.wait())
+        .await
+        .map_err(|_| FirmwareError::Timeout)?;
+
+    match status.error {
+        FirmwareError::None => Ok(status),
+        e => Err(e),
+    }
+}
+
+/// Stream a firmware image of `size` bytes stored at `offset` in `reader` to the headlight on `side`.
+///
+/// Each step waits for the headlight to acknowledge before continuing,
+/// the image is only applied if the headlight's CRC matches `crc`.
+/// `on_progress` is called with the number of bytes acknowledged so far.
+pub async fn push_firmware<R: ReadNorFlash>(
+    headlights: &Headlights,
+    side: Side,
+    reader: &mut R,
+    offset: u32,
+    size: u32,
+    crc: u32,
+    mut on_progress: impl FnMut(u32),
+) -> Result<(), FirmwareError> {
+    let queue = headlights.queue(side);
+    let acknowledgement = firmware_status(side);
+
+    send_and_wait(
+        queue,
+        acknowledgement,
+        FirmwareBegin { size, crc },
+        BEGIN_TIMEOUT,
+    )
+    .await?;
+
+    let mut sent = 0;
+
+    while sent < size {
+        let mut data = [0xff; FIRMWARE_CHUNK_SIZE];
+        let len = (size - sent).min(FIRMWARE_CHUNK_SIZE as u32) as usize;
+
+        if reader.read(offset + sent, &mut data[..len]).await.is_err() {
+            queue.send(FirmwareCommit::Abort.into()).await;
+            return Err(FirmwareError::Flash);
+        }
+
+        let result = send_and_wait(
+            queue,
+            acknowledgement,
+            FirmwareChunk { offset: sent, data },
+            CHUNK_TIMEOUT,
+        )
+        .await;
+
+        match result {
+            Ok(status) => {
+                sent = status.received;
+                on_progress(sent);
+            }
+            Err(e) => {
+                queue.send(FirmwareCommit::Abort.into()).await;
+                return Err(e);
+            }
+        }
+    }
+
+    // the headlight resets shortly after acknowledging
+    send_and_wait(queue, acknowledgement, FirmwareCommit::Apply, CHUNK_TIMEOUT)
+        .await
+        .map(|_| ())
+}
+
+struct Session {
+    size: u32,
+    crc: u32,
+    received: u32,
+}
+
+/// Buffers an image received over BLE in the staging region of the relay's flash.
+struct Staging {
+    flash: &'static SharedFlash,
+    session: Option<Session>,
+}
+
+impl Staging {
+    const fn new(flash: &'static SharedFlash) -> Self {
+        Self {
+            flash,
+            session: None,
+        }
+    }
+
+    async fn begin(&mut self, begin: FirmwareBegin) -> Result<(), FirmwareError> {
+        if begin.size > STAGING_SIZE {
+            return Err(FirmwareError::TooLarge);
+        }
+
+        self.session = None;
+        self.flash
+            .lock()
+            .await
+            .erase(STAGING_START, STAGING_START + STAGING_SIZE)
+            .await
+            .map_err(|_| FirmwareError::Flash)?;
+        self.session = Some(Session {
+            size: begin.size,
+            crc: begin.crc,
+            received: 0,
+        });
+
+        Ok(())
+    }
+
+    async fn write(&mut self, chunk: DfuChunk) -> Result<(), FirmwareError> {
+        let session = self.session.as_mut().ok_or(FirmwareError::NotStarted)?;
+
+        if chunk.offset != session.received {
+            return Err(FirmwareError::OutOfOrder);
+        }
+
+        if session.received >= session.size {
+            return Err(FirmwareError::TooLarge);
+        }
+
+        // the staging size is a multiple of the chunk size, so the padding always fits
+        self.flash
+            .lock()
+            .await
+            .write(STAGING_START + chunk.offset, &chunk.data)
+            .await
+            .map_err(|_| FirmwareError::Flash)?;
+        session.received += min(DFU_CHUNK_SIZE as u32, session.size - session.received);
+
+        Ok(())
+    }
+
+    /// Check the image as it was written to flash, not as it was received.
+    async fn verify(&mut self) -> Result<(u32, u32), FirmwareError> {
+        let session = self.session.as_ref().ok_or(FirmwareError::NotStarted)?;
+
+        if session.received != session.size {
+            return Err(FirmwareError::Incomplete);
+        }
+
+        let mut flash = self.flash.lock().await;
+        let mut digest = IMAGE_CRC.digest();
+        let mut buf = [0; DFU_CHUNK_SIZE];
+        let mut read = 0;
+
+        while read < session.size {
+            let len = min(DFU_CHUNK_SIZE as u32, session.size - read) as usize;
+
+            flash
+                .read(STAGING_START + read, &mut buf[..len])
+                .await
+                .map_err(|_| FirmwareError::Flash)?;
+            digest.update(&buf[..len]);
+            read += len as u32;
+        }
+
+        if digest.finalize() != session.crc {
+            return Err(FirmwareError::Crc);
+        }
+
+        Ok((session.size, session.crc))
+    }
+}
+
+fn report(server: &Server, conns: &[Connection], progress: DfuProgress) {
+    let data = progress.serialize();
+
+    server.dfu.progress_set(&data).ok();
+
+    for conn in conns {
+        server.dfu.progress_notify(conn, &data).ok();
+    }
+}
+
+#[embassy_executor::task]
+pub async fn dfu_worker(
+    flash: &'static SharedFlash,
+    ble: &'static BLE,
+    headlights: &'static Headlights,
+) {
+    let mut staging = Staging::new(flash);
+
+    loop {
+        let request = DFU_QUEUE.recv().await;
+
+        let (stage, result) = match request {
+            DfuRequest::Begin(begin) => (DfuStage::Receiving, staging.begin(begin).await),
+            DfuRequest::Chunk(chunk) => (DfuStage::Receiving, staging.write(chunk).await),
+            DfuRequest::Commit(FirmwareCommit::Abort) => {
+                staging.session = None;
+                (DfuStage::Idle, Ok(()))
+            }
+            DfuRequest::Commit(FirmwareCommit::Apply) => {
+                let conns = ble.get_conns().await;
+
+                report(
+                    ble.get_server(),
+                    &conns,
+                    DfuProgress {
+                        stage: DfuStage::Verifying,
+                        ..Default::default()
+                    },
+                );
+
+                match staging.verify().await {
+                    Ok((size, crc)) => {
+                        let mut result = Ok(());
+
+                        // one side at a time, progress covers both pushes
+                        for (i, side) in Side::BOTH.into_iter().enumerate() {
+                            info!("Pushing {} byte image to {} headlight.", size, side);
+                            log::record(
+                                LogLevel::Info,
+                                LogModule::Update,
+                                update::BEGUN,
+                                size,
+                                side as u32,
+                            );
+
+                            result = push_firmware(
+                                headlights,
+                                side,
+                                &mut *staging.flash.lock().await,
+                                STAGING_START,
+                                size,
+                                crc,
+                                |sent| {
+                                    report(
+                                        ble.get_server(),
+                                        &conns,
+                                        DfuProgress {
+                                            stage: DfuStage::Pushing,
+                                            transferred: i as u32 * size + sent,
+                                            size: Side::BOTH.len() as u32 * size,
+                                            error: FirmwareError::None,
+                                        },
+                                    )
+                                },
+                            )
+                            .await;
+
+                            if result.is_err() {
+                                break;
+                            }
+                        }
+
+                        (DfuStage::Done, result)
+                    }
+                    Err(e) => (DfuStage::Verifying, Err(e)),
+                }
+            }
+        };
+
+        if let Err(e) = result {
+            warn!("Firmware update failed with error: {}", e);
+            log::record(
+                LogLevel::Error,
+                LogModule::Update,
+                update::FAILED,
+                e as u32,
+                0,
+            );
+        }
+
+        let (transferred, size) = staging
+            .session
+            .as_ref()
+            .map_or((0, 0), |session| (session.received, session.size));
+
+        report(
+            ble.get_server(),
+            &ble.get_conns().await,
+            DfuProgress {
+                stage,
+                transferred,
+                size,
+                error: result.err().unwrap_or_default(),
+            },
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use core::{cell::RefCell, mem};
+use embassy_sync::{
+    blocking_mutex::{raw::ThreadModeRawMutex, Mutex},
+    signal::Signal,
+};
+use heapless::Vec;
+use nrf_softdevice::{ble::get_address, raw};
+
+use crate::{
+    fmt::{error, info, unwrap},
+    utils::{
+        beacon::set_adv_name,
+        ble::BLE,
+        storage::{load_identity, store_identity, Identity, SharedFlash, MAX_NAME_LEN},
+    },
+};
+
+pub const DEFAULT_NAME: &[u8] = b"Headlights V2";
+
+/// A space and four hex digits of the device address.
+const SUFFIX_LEN: usize = 5;
+
+pub const MAX_FULL_NAME_LEN: usize = MAX_NAME_LEN + SUFFIX_LEN;
+
+pub type FullName = Vec<u8, MAX_FULL_NAME_LEN>;
+
+static IDENTITY: Mutex<ThreadModeRawMutex, RefCell<Identity>> =
+    Mutex::new(RefCell::new(Identity {
+        name: Vec::new(),
+        suffix: false,
+    }));
+
+/// Signalled whenever the identity changes and must be applied and persisted.
+static IDENTITY_CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+pub fn identity() -> Identity {
+    IDENTITY.lock(|identity| identity.borrow().clone())
+}
+
+pub fn update_identity(f: impl FnOnce(&mut Identity)) {
+    IDENTITY.lock(|identity| f(&mut identity.borrow_mut()));
+    IDENTITY_CHANGED.signal(());
+}
+
+fn full_name(ble: &BLE, identity: &Identity) -> FullName {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    let mut name = FullName::new();
+    unwrap!(name.extend_from_slice(&identity.name));
+
+    if identity.suffix {
+        // the least significant bytes differ the most between units
+        let address = get_address(ble.get_softdevice()).bytes;
+
+        unwrap!(name.push(b' '));
+
+        for byte in [address[1], address[0]] {
+            unwrap!(name.push(HEX[(byte >> 4) as usize]));
+            unwrap!(name.push(HEX[(byte & 0xf) as usize]));
+        }
+    }
+
+    name
+}
+
+/// Use the identity for the GAP device name, advertising and the name characteristics.
+fn apply_identity(ble: &BLE, identity: &Identity) {
+    let name = full_name(ble, identity);
+    // the name is not writable through GAP, only through the (authenticated) characteristic
+    let write_perm: raw::ble_gap_conn_sec_mode_t = unsafe { mem::zeroed() };
+
+    let ret =
+        unsafe { raw::sd_ble_gap_device_name_set(&write_perm, name.as_ptr(), name.len() as u16) };
+
+    if ret != raw::NRF_SUCCESS {
+        error!("Device name could not be set, error code: {}", ret);
+    }
+
+    set_adv_name(&name);
+
+    let server = ble.get_server();
+    server.headlight.name_set(&identity.name).ok();
+    server.headlight.name_suffix_set(&identity.suffix).ok();
+}
+
+pub async fn init_identity(flash: &'static SharedFlash, ble: &'static BLE) {
+    let identity = load_identity(flash).await.unwrap_or_else(|| Identity {
+        name: unwrap!(Vec::from_slice(DEFAULT_NAME)),
+        suffix: true,
+    });
+
+    IDENTITY.lock(|current| *current.borrow_mut() = identity.clone());
+    apply_identity(ble, &identity);
+}
+
+#[embassy_executor::task]
+pub async fn identity_worker(flash: &'static SharedFlash, ble: &'static BLE) {
+    loop {
+        IDENTITY_CHANGED.wait().await;
+
+        let identity = identity();
+
+        apply_identity(ble, &identity);
+
+        if let Err(e) = store_identity(flash, &identity).await {
+            error!("Identity failed to store with error: {:?}", e);
+        } else {
+            info!("Identity stored.");
+        }
+    }
+}
@@ -0,0 +1,56 @@
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::{raw::ThreadModeRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+
+/// How long control writes from other connections are rejected after the holder's last write.
+const LEASE_DURATION: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy)]
+struct Lease {
+    handle: u16,
+    expires: Instant,
+}
+
+/// Arbitrates `control` writes between connected centrals.
+///
+/// The last central to write control holds a lease, which is renewed by each of its writes
+/// and released when it expires or the holder disconnects.
+/// Writes from any other central are rejected while the lease is held.
+pub struct ControlLease {
+    lease: Mutex<ThreadModeRawMutex, Cell<Option<Lease>>>,
+}
+
+impl ControlLease {
+    pub const fn new() -> Self {
+        Self {
+            lease: Mutex::new(Cell::new(None)),
+        }
+    }
+
+    /// Returns whether the connection may write control, acquiring or renewing the lease if so.
+    pub fn acquire(&self, handle: u16) -> bool {
+        let now = Instant::now();
+
+        self.lease.lock(|lease| match lease.get() {
+            Some(held) if held.handle != handle && held.expires > now => false,
+            _ => {
+                lease.set(Some(Lease {
+                    handle,
+                    expires: now + LEASE_DURATION,
+                }));
+
+                true
+            }
+        })
+    }
+
+    pub fn release(&self, handle: u16) {
+        self.lease.lock(|lease| {
+            if let Some(held) = lease.get() {
+                if held.handle == handle {
+                    lease.set(None);
+                }
+            }
+        })
+    }
+}
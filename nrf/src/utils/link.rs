@@ -0,0 +1,160 @@
+use common::{
+    command::{
+        commands::{Heartbeat, LinkStatus, Status},
+        reader::{LinkEvent, LinkObserver},
+    },
+    types::{LogLevel, LogModule, Mode, Side},
+    utils::log::codes::link,
+};
+use core::cell::Cell;
+use embassy_sync::{
+    blocking_mutex::{raw::ThreadModeRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{with_timeout, Duration, Timer};
+use tiny_serde::{Deserialize, Serialize};
+
+use crate::{
+    command::writer::Headlights,
+    fmt::{info, warn},
+    utils::{
+        ble::{side_service, BLE},
+        clock::timestamp,
+        log,
+    },
+};
+
+const HEARTBEAT_PERIOD: Duration = Duration::from_secs(1);
+/// The headlight is considered silent if nothing is received for this long after a heartbeat.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_millis(500);
+
+static LINK_MONITORS: [LinkMonitor; 2] = [LinkMonitor::new(), LinkMonitor::new()];
+
+pub fn link_monitor(side: Side) -> &'static LinkMonitor {
+    &LINK_MONITORS[side as usize]
+}
+
+/// Whether `side` last reported being in standby.
+pub fn in_standby(ble: &BLE, side: Side) -> bool {
+    side_service!(ble.get_server(), side, |service| service.status_get())
+        .ok()
+        .and_then(Status::deserialize)
+        .is_some_and(|status| status.mode == Mode::Standby)
+}
+
+/// Link quality counters, updated by the command reader.
+pub struct LinkMonitor {
+    status: Mutex<ThreadModeRawMutex, Cell<LinkStatus>>,
+    /// Signalled whenever a valid command is received from the headlight.
+    activity: Signal<ThreadModeRawMutex, ()>,
+}
+
+impl LinkMonitor {
+    const fn new() -> Self {
+        Self {
+            status: Mutex::new(Cell::new(LinkStatus {
+                alive: false,
+                received: 0,
+                crc_failures: 0,
+                malformed: 0,
+                overflows: 0,
+            })),
+            activity: Signal::new(),
+        }
+    }
+
+    /// Record whether the headlight answered, returning the new status and whether liveness changed.
+    fn set_alive(&self, alive: bool) -> (LinkStatus, bool) {
+        self.status.lock(|status| {
+            let mut new = status.get();
+            let changed = new.alive != alive;
+
+            new.alive = alive;
+            status.set(new);
+
+            (new, changed)
+        })
+    }
+}
+
+impl LinkObserver for &'static LinkMonitor {
+    fn observe(&mut self, event: LinkEvent) {
+        self.status.lock(|status| {
+            let mut new = status.get();
+
+            match event {
+                LinkEvent::Received => new.received = new.received.wrapping_add(1),
+                LinkEvent::CrcFailure => new.crc_failures = new.crc_failures.wrapping_add(1),
+                LinkEvent::Malformed => new.malformed = new.malformed.wrapping_add(1),
+                LinkEvent::Overflow => new.overflows = new.overflows.wrapping_add(1),
+            }
+
+            status.set(new);
+        });
+
+        if let LinkEvent::Received = event {
+            self.activity.signal(());
+        }
+    }
+}
+
+#[embassy_executor::task(pool_size = 2)]
+pub async fn link_monitor_worker(
+    ble: &'static BLE,
+    headlights: &'static Headlights,
+    side: Side,
+) -> ! {
+    let monitor = link_monitor(side);
+    let queue = headlights.queue(side);
+    let mut seq: u32 = 0;
+
+    loop {
+        // heartbeats would wake a headlight in standby, it reports its status once woken
+        if in_standby(ble, side) {
+            Timer::after(HEARTBEAT_PERIOD).await;
+            continue;
+        }
+
+        monitor.activity.reset();
+        queue.send(Heartbeat { seq }.into()).await;
+        seq = seq.wrapping_add(1);
+
+        // any traffic counts, the echo is just the traffic guaranteed to occur
+        let alive = with_timeout(HEARTBEAT_TIMEOUT, monitor.activity.wait())
+            .await
+            .is_ok();
+
+        let (status, changed) = monitor.set_alive(alive);
+        let data = status.serialize();
+
+        side_service!(ble.get_server(), side, |service| {
+            service.link_status_set(&data).ok();
+        });
+
+        if changed {
+            if alive {
+                info!("{} headlight link established at {}.", side, timestamp());
+                log::record(
+                    LogLevel::Info,
+                    LogModule::Link,
+                    link::ESTABLISHED,
+                    side as u32,
+                    0,
+                );
+            } else {
+                warn!("{} headlight link lost at {}.", side, timestamp());
+                log::record(LogLevel::Warn, LogModule::Link, link::LOST, side as u32, 0);
+            }
+
+            let conns = ble.get_conns().await;
+
+            side_service!(ble.get_server(), side, |service| {
+                for conn in conns.iter() {
+                    service.link_status_notify(conn, &data).ok();
+                }
+            });
+        }
+
+        Timer::after(HEARTBEAT_PERIOD).await;
+    }
+}
@@ -0,0 +1,84 @@
+use common::{
+    command::commands::Log,
+    types::{LogLevel, LogModule, LogSource, Side},
+    utils::log::LogRing,
+};
+use core::cell::RefCell;
+use embassy_sync::{
+    blocking_mutex::{raw::ThreadModeRawMutex, Mutex},
+    channel::Channel,
+};
+use embassy_time::Instant;
+use heapless::Vec;
+use nrf_softdevice::ble::Connection;
+use tiny_serde::Serialize;
+
+use crate::utils::{ble::BLE, clock::timestamp};
+
+/// Holds the relay's entries and those forwarded by both headlights.
+const LOG_SIZE: usize = 32;
+
+static LOG: Mutex<ThreadModeRawMutex, RefCell<LogRing<LOG_SIZE>>> =
+    Mutex::new(RefCell::new(LogRing::new()));
+
+/// Entries waiting to be notified to the app.
+static NOTIFY: Channel<ThreadModeRawMutex, Log, 8> = Channel::new();
+
+fn push(entry: Log) {
+    LOG.lock(|log| log.borrow_mut().push(entry));
+    // if notifications cannot keep up, the entry is still replayed on the next subscription
+    NOTIFY.try_send(entry).ok();
+}
+
+pub fn record(level: LogLevel, module: LogModule, code: u8, arg0: u32, arg1: u32) {
+    push(Log {
+        time: timestamp(),
+        uptime: Instant::now().as_millis() as u32,
+        source: LogSource::Relay,
+        level,
+        module,
+        code,
+        arg0,
+        arg1,
+    });
+}
+
+/// Keep an entry forwarded by the headlight on `side`.
+pub fn forward(side: Side, entry: Log) {
+    push(Log {
+        source: side.into(),
+        ..entry
+    });
+}
+
+/// Every buffered entry, oldest first.
+pub fn entries() -> Vec<Log, LOG_SIZE> {
+    LOG.lock(|log| log.borrow().iter().copied().collect())
+}
+
+/// Notify the buffered entries to a connection that just subscribed.
+pub fn replay(ble: &BLE, conn: &Connection) {
+    for entry in entries() {
+        // stop once the notification queue is full, the rest would only fail too
+        if ble
+            .get_server()
+            .headlight
+            .log_notify(conn, &entry.serialize())
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn log_worker(ble: &'static BLE) {
+    loop {
+        let data = NOTIFY.receive().await.serialize();
+
+        for conn in ble.get_conns().await.iter() {
+            // not every connection subscribes
+            ble.get_server().headlight.log_notify(conn, &data).ok();
+        }
+    }
+}
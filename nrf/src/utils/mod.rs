@@ -1,2 +1,13 @@
+pub mod beacon;
 pub mod ble;
+pub mod clock;
+pub mod dfu;
+pub mod identity;
+pub mod lease;
+pub mod link;
+pub mod log;
+pub mod power;
+pub mod schedule;
+pub mod security;
+pub mod storage;
 pub mod uart;
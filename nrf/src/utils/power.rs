@@ -0,0 +1,40 @@
+use core::cell::Cell;
+use embassy_sync::blocking_mutex::{raw::ThreadModeRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+
+/// Advertising interval while recently active, in units of 0.625 ms (100 ms).
+const FAST_INTERVAL: u32 = 160;
+/// Advertising interval once inactive, in units of 0.625 ms (2 s).
+const SLOW_INTERVAL: u32 = 3200;
+/// How long advertising stays fast after the last activity.
+const FAST_DURATION: Duration = Duration::from_secs(30);
+
+/// Advertises quickly around activity (boot, connections coming and going)
+/// and slowly otherwise, since the vehicle may sit parked for weeks.
+pub struct PowerPolicy {
+    last_activity: Mutex<ThreadModeRawMutex, Cell<Instant>>,
+}
+
+impl PowerPolicy {
+    pub const fn new() -> Self {
+        Self {
+            last_activity: Mutex::new(Cell::new(Instant::from_ticks(0))),
+        }
+    }
+
+    pub fn activity(&self) {
+        self.last_activity
+            .lock(|last_activity| last_activity.set(Instant::now()));
+    }
+
+    /// The advertising interval to use now, and when it slows down (if it has not already).
+    pub fn advertising_interval(&self) -> (u32, Option<Instant>) {
+        let slowdown = self.last_activity.lock(Cell::get) + FAST_DURATION;
+
+        if Instant::now() < slowdown {
+            (FAST_INTERVAL, Some(slowdown))
+        } else {
+            (SLOW_INTERVAL, None)
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use common::{
+    command::commands::{serialize_schedule, Control, Reset, Rule, Schedule, MAX_RULES},
+    types::{AmbientLight, LogLevel, LogModule, RuleAction, RuleTrigger, Side, VehicleSignal},
+    utils::{bundles::FromHeadlightBundle, log::codes::schedule as schedule_codes},
+};
+use core::{cell::Cell, future::pending};
+use embassy_futures::select::{select3, Either3};
+use embassy_sync::{
+    blocking_mutex::{raw::ThreadModeRawMutex, Mutex},
+    channel::Channel,
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::{
+    command::{extension::Execute, writer::Headlights},
+    fmt::{error, info, warn},
+    utils::{
+        ble::BLE,
+        clock::timestamp,
+        log,
+        storage::{load_schedule, store_schedule, SharedFlash},
+    },
+};
+
+const EMPTY_RULE: Rule = Rule {
+    trigger: RuleTrigger::Disabled,
+    delay: 0,
+    action: RuleAction::SetTarget,
+    target: 0,
+};
+
+static SCHEDULE: Mutex<ThreadModeRawMutex, Cell<Schedule>> =
+    Mutex::new(Cell::new([EMPTY_RULE; MAX_RULES]));
+
+/// Signalled whenever the schedule changes and must be restarted and persisted.
+static SCHEDULE_CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// Triggers observed from the headlights and the app, waiting to be matched against the rules.
+static TRIGGERS: Channel<ThreadModeRawMutex, RuleTrigger, 8> = Channel::new();
+
+pub fn schedule() -> Schedule {
+    SCHEDULE.lock(Cell::get)
+}
+
+pub fn set_schedule(schedule: Schedule) {
+    SCHEDULE.lock(|current| current.set(schedule));
+    SCHEDULE_CHANGED.signal(());
+}
+
+pub fn observe(trigger: RuleTrigger) {
+    if TRIGGERS.try_send(trigger).is_err() {
+        warn!(
+            "Schedule trigger {} was dropped (triggers are arriving too fast).",
+            trigger
+        );
+    }
+}
+
+pub fn observe_control(control: &Control) {
+    observe(if control.target > 0 {
+        RuleTrigger::LightsOn
+    } else {
+        RuleTrigger::LightsOff
+    });
+}
+
+/// Observe whatever trigger a command from a headlight amounts to.
+pub fn observe_headlight(bundle: &FromHeadlightBundle) {
+    match bundle {
+        FromHeadlightBundle::Control(control) => observe_control(control),
+        FromHeadlightBundle::InputEvent(_) => observe(RuleTrigger::Idle),
+        FromHeadlightBundle::SignalEvent(event) if event.signal == VehicleSignal::Ignition => {
+            observe(if event.active {
+                RuleTrigger::IgnitionOn
+            } else {
+                RuleTrigger::IgnitionOff
+            })
+        }
+        FromHeadlightBundle::AmbientEvent(event) => observe(match event.light {
+            AmbientLight::Night => RuleTrigger::Dusk,
+            AmbientLight::Day => RuleTrigger::Dawn,
+        }),
+        _ => {}
+    }
+}
+
+pub async fn init_schedule(flash: &'static SharedFlash, ble: &'static BLE) {
+    let schedule = load_schedule(flash)
+        .await
+        .unwrap_or([EMPTY_RULE; MAX_RULES]);
+
+    SCHEDULE.lock(|current| current.set(schedule));
+
+    ble.get_server()
+        .headlight
+        .schedule_set(&serialize_schedule(&schedule))
+        .ok();
+}
+
+/// The latest of each opposing pair of triggers.
+///
+/// Both headlights report the same changes, so repeats are ignored.
+#[derive(Default)]
+struct States {
+    lights: Option<RuleTrigger>,
+    ignition: Option<RuleTrigger>,
+    ambient: Option<RuleTrigger>,
+}
+
+impl States {
+    /// Record `trigger`, returning whether it is a change.
+    fn update(&mut self, trigger: RuleTrigger) -> bool {
+        let state = match trigger {
+            RuleTrigger::Disabled | RuleTrigger::Idle => return true,
+            RuleTrigger::LightsOn | RuleTrigger::LightsOff => &mut self.lights,
+            RuleTrigger::IgnitionOn | RuleTrigger::IgnitionOff => &mut self.ignition,
+            RuleTrigger::Dusk | RuleTrigger::Dawn => &mut self.ambient,
+        };
+
+        state.replace(trigger) != Some(trigger)
+    }
+}
+
+async fn execute(ble: &BLE, headlights: &Headlights, index: usize, rule: &Rule) {
+    info!("Executing schedule rule at {}: {}.", timestamp(), rule);
+    log::record(
+        LogLevel::Info,
+        LogModule::Schedule,
+        schedule_codes::EXECUTED,
+        index as u32,
+        rule.action as u32,
+    );
+
+    match rule.action {
+        RuleAction::SetTarget => {
+            let control = Control {
+                target: rule.target,
+            };
+
+            headlights.send_both(control.clone().into()).await;
+
+            // the headlights do not report controls sent to them, so the app is told here
+            let conns = ble.get_conns().await;
+
+            for side in Side::BOTH {
+                if let Err(e) = control.clone().run(ble.get_server(), &conns, side) {
+                    warn!("Scheduled control failed to dispatch with error: {}", e);
+                }
+            }
+        }
+        RuleAction::Standby => headlights.send_both(Reset::Standby.into()).await,
+    }
+}
+
+#[embassy_executor::task]
+pub async fn schedule_worker(
+    flash: &'static SharedFlash,
+    ble: &'static BLE,
+    headlights: &'static Headlights,
+) {
+    // when each rule's action is due, if its trigger has happened
+    let mut deadlines = [None::<Instant>; MAX_RULES];
+    let mut states = States::default();
+
+    // idle rules count from boot, even if nothing ever happens
+    observe(RuleTrigger::Idle);
+
+    loop {
+        let next = deadlines.iter().flatten().min().copied();
+        let due = async {
+            match next {
+                Some(at) => Timer::at(at).await,
+                None => pending().await,
+            }
+        };
+
+        match select3(TRIGGERS.receive(), due, SCHEDULE_CHANGED.wait()).await {
+            Either3::First(trigger) => {
+                if !states.update(trigger) {
+                    continue;
+                }
+
+                let now = Instant::now();
+
+                for (rule, deadline) in schedule().iter().zip(&mut deadlines) {
+                    if rule.trigger == trigger {
+                        *deadline = Some(now + Duration::from_secs(rule.delay.into()));
+                    } else if rule.trigger.opposite() == Some(trigger) {
+                        *deadline = None;
+                    }
+                }
+            }
+            Either3::Second(_) => {
+                let now = Instant::now();
+
+                for (index, (rule, deadline)) in schedule().iter().zip(&mut deadlines).enumerate() {
+                    if deadline.is_some_and(|at| at <= now) {
+                        *deadline = None;
+                        execute(ble, headlights, index, rule).await;
+                    }
+                }
+            }
+            Either3::Third(_) => {
+                // rules may have moved between slots, so nothing pending carries over
+                deadlines = [None; MAX_RULES];
+                // writing the schedule is activity too, and restarts idle rules
+                observe(RuleTrigger::Idle);
+
+                if let Err(e) = store_schedule(flash, &schedule()).await {
+                    error!("Schedule failed to store with error: {:?}", e);
+                } else {
+                    info!("Schedule stored.");
+                    log::record(
+                        LogLevel::Info,
+                        LogModule::Schedule,
+                        schedule_codes::STORED,
+                        0,
+                        0,
+                    );
+                }
+            }
+        }
+    }
+}
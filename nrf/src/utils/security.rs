@@ -0,0 +1,337 @@
+use common::{
+    command::commands::{Config, Control},
+    types::{Gesture, Side, VehicleSignal},
+    utils::bundles::FromHeadlightBundle,
+};
+use core::{
+    cell::{Cell, RefCell},
+    cmp::min,
+};
+use embassy_sync::{
+    blocking_mutex::{raw::ThreadModeRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant, Timer};
+use nrf_softdevice::{
+    ble::{
+        security::{IoCapabilities, SecurityHandler},
+        Connection, EncryptionInfo, IdentityKey, MasterId,
+    },
+    random_bytes, raw, Softdevice,
+};
+use p256::{
+    ecdh::diffie_hellman,
+    elliptic_curve::sec1::{FromEncodedPoint, ToEncodedPoint},
+    EncodedPoint, PublicKey, SecretKey,
+};
+use static_cell::StaticCell;
+use tiny_serde::Deserialize;
+
+use crate::{
+    command::writer::Headlights,
+    fmt::{error, info, unwrap, warn},
+    utils::{
+        ble::BLE,
+        storage::{load_bonds, store_bonds, Bond, Bonds, SharedFlash},
+    },
+};
+
+static BONDER: StaticCell<Bonder> = StaticCell::new();
+
+/// Signalled whenever the bond table changes and must be written back to flash.
+static BONDS_CHANGED: Signal<ThreadModeRawMutex, ()> = Signal::new();
+
+/// The passkey of the pairing in progress, waiting to be shown on the headlights.
+static PASSKEY: Signal<ThreadModeRawMutex, [u8; 6]> = Signal::new();
+
+/// Whether pairing is allowed, see [`observe_for_pairing`].
+static PAIRING_GATE: Mutex<ThreadModeRawMutex, Cell<PairingGate>> =
+    Mutex::new(Cell::new(PairingGate {
+        pressed: None,
+        ignition: false,
+    }));
+
+/// How long pairing stays open after a headlight's button is pressed.
+const PAIRING_WINDOW: Duration = Duration::from_secs(60);
+
+/// Target the headlights flash to while showing a passkey, unless their max target is lower.
+const FLASH_TARGET: u16 = 300;
+const FLASH_ON: Duration = Duration::from_millis(150);
+const FLASH_OFF: Duration = Duration::from_millis(150);
+const DIGIT_GAP: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy)]
+struct PairingGate {
+    /// When a headlight's button was last pressed
+    pressed: Option<Instant>,
+    /// Whether the vehicle is on (if the ignition is wired to a headlight)
+    ignition: bool,
+}
+
+impl PairingGate {
+    /// Pairing flashes the headlights, so it needs someone at the vehicle, which must not be driving.
+    fn open(&self) -> bool {
+        !self.ignition
+            && self
+                .pressed
+                .is_some_and(|pressed| pressed.elapsed() < PAIRING_WINDOW)
+    }
+}
+
+fn pairing_open() -> bool {
+    PAIRING_GATE.lock(|gate| gate.get().open())
+}
+
+/// Open pairing for a while after a headlight's button is pressed, and close it while the ignition is on.
+pub fn observe_for_pairing(bundle: &FromHeadlightBundle) {
+    PAIRING_GATE.lock(|gate| {
+        let mut new = gate.get();
+
+        match bundle {
+            FromHeadlightBundle::InputEvent(event) => match event.gesture {
+                Gesture::ShortPress | Gesture::LongPress | Gesture::DoublePress => {
+                    new.pressed = Some(Instant::now())
+                }
+                Gesture::HighBeam | Gesture::LowBeam => {}
+            },
+            FromHeadlightBundle::SignalEvent(event) if event.signal == VehicleSignal::Ignition => {
+                new.ignition = event.active;
+
+                if event.active {
+                    new.pressed = None;
+                }
+            }
+            _ => {}
+        }
+
+        gate.set(new);
+    });
+}
+
+/// The SoftDevice keeps P-256 coordinates little endian, the reverse of SEC1.
+fn reversed(bytes: &[u8]) -> [u8; 32] {
+    let mut reversed = [0; 32];
+    reversed.copy_from_slice(bytes);
+    reversed.reverse();
+    reversed
+}
+
+/// The key pair for LE Secure Connections, generated at boot.
+struct LescKeys {
+    secret: SecretKey,
+    public: raw::ble_gap_lesc_p256_pk_t,
+}
+
+impl LescKeys {
+    fn generate(sd: &Softdevice) -> Self {
+        let secret = loop {
+            let mut seed = [0; 32];
+            unwrap!(random_bytes(sd, &mut seed));
+
+            // the rare seed outside the curve's order is drawn again
+            if let Ok(secret) = SecretKey::from_slice(&seed) {
+                break secret;
+            }
+        };
+
+        let point = secret.public_key().to_encoded_point(false);
+        let mut public = raw::ble_gap_lesc_p256_pk_t { pk: [0; 64] };
+
+        // an uncompressed point always has both coordinates
+        public.pk[..32].copy_from_slice(&reversed(unwrap!(point.x())));
+        public.pk[32..].copy_from_slice(&reversed(unwrap!(point.y())));
+
+        Self { secret, public }
+    }
+
+    /// The shared secret with a peer, if its key is a point on the curve.
+    fn dhkey(&self, peer: &raw::ble_gap_lesc_p256_pk_t) -> Option<raw::ble_gap_lesc_dhkey_t> {
+        let point = EncodedPoint::from_affine_coordinates(
+            &reversed(&peer.pk[..32]).into(),
+            &reversed(&peer.pk[32..]).into(),
+            false,
+        );
+        let peer = Option::<PublicKey>::from(PublicKey::from_encoded_point(&point))?;
+        let shared = diffie_hellman(self.secret.to_nonzero_scalar(), peer.as_affine());
+
+        Some(raw::ble_gap_lesc_dhkey_t {
+            key: reversed(shared.raw_secret_bytes()),
+        })
+    }
+}
+
+/// Pairs with LE Secure Connections and a passkey shown on the headlights,
+/// so only someone at the vehicle (who pressed a headlight's button) can bond with the relay.
+pub struct Bonder {
+    bonds: Mutex<ThreadModeRawMutex, RefCell<Bonds>>,
+    lesc: LescKeys,
+}
+
+impl Bonder {
+    pub async fn init(flash: &'static SharedFlash, sd: &Softdevice) -> &'static Self {
+        BONDER.init(Self {
+            bonds: Mutex::new(RefCell::new(load_bonds(flash).await)),
+            lesc: LescKeys::generate(sd),
+        })
+    }
+}
+
+impl SecurityHandler for Bonder {
+    fn io_capabilities(&self) -> IoCapabilities {
+        IoCapabilities::DisplayOnly
+    }
+
+    fn can_bond(&self, _conn: &Connection) -> bool {
+        pairing_open()
+    }
+
+    fn display_passkey(&self, passkey: &[u8; 6]) {
+        // without the passkey the central cannot authenticate, so the pairing fails
+        if !pairing_open() {
+            warn!("Pairing requested without a button press (or with the ignition on), the passkey is withheld.");
+            return;
+        }
+
+        // drawn at random by the SoftDevice for every pairing
+        info!("Pairing requested, showing the passkey on the headlights.");
+        PASSKEY.signal(*passkey);
+    }
+
+    fn lesc_public_key(&self) -> Option<&raw::ble_gap_lesc_p256_pk_t> {
+        Some(&self.lesc.public)
+    }
+
+    fn lesc_dhkey(
+        &self,
+        _conn: &Connection,
+        peer: &raw::ble_gap_lesc_p256_pk_t,
+    ) -> Option<raw::ble_gap_lesc_dhkey_t> {
+        let dhkey = self.lesc.dhkey(peer);
+
+        if dhkey.is_none() {
+            warn!("Peer's public key is not on the curve, pairing is rejected.");
+        }
+
+        dhkey
+    }
+
+    fn on_bonded(
+        &self,
+        _conn: &Connection,
+        master_id: MasterId,
+        key: EncryptionInfo,
+        peer_id: IdentityKey,
+    ) {
+        self.bonds.lock(|bonds| {
+            let mut bonds = bonds.borrow_mut();
+
+            // a peer that bonds again replaces its old keys, otherwise the oldest bond is evicted
+            bonds.retain(|bond| bond.peer_id.addr != peer_id.addr);
+
+            if bonds.is_full() {
+                bonds.remove(0);
+            }
+
+            bonds
+                .push(Bond {
+                    master_id,
+                    key,
+                    peer_id,
+                })
+                .ok();
+        });
+
+        info!("Bonded with new peer.");
+        BONDS_CHANGED.signal(());
+    }
+
+    fn get_key(&self, conn: &Connection, master_id: MasterId) -> Option<EncryptionInfo> {
+        // LE Secure Connections keys have no master ID, so those bonds are told apart by peer
+        let lesc = master_id.ediv == 0 && master_id.rand == [0; 8];
+        let peer = conn.peer_address();
+
+        self.bonds.lock(|bonds| {
+            bonds
+                .borrow()
+                .iter()
+                .find(|bond| {
+                    if lesc {
+                        // phones usually use resolvable private addresses, resolved with the stored IRK
+                        bond.peer_id.is_match(peer)
+                    } else {
+                        bond.master_id == master_id
+                    }
+                })
+                .map(|bond| bond.key)
+        })
+    }
+}
+
+#[embassy_executor::task]
+pub async fn bond_storage_worker(flash: &'static SharedFlash, bonder: &'static Bonder) {
+    loop {
+        BONDS_CHANGED.wait().await;
+
+        let bonds = bonder.bonds.lock(|bonds| bonds.borrow().clone());
+
+        if let Err(e) = store_bonds(flash, &bonds).await {
+            error!("Bonds failed to store with error: {:?}", e);
+        }
+    }
+}
+
+async fn set_both(headlights: &Headlights, target: u16) {
+    // straight to the queues, the flashes are not a change of control
+    // (nothing is mirrored and no schedule rule is triggered)
+    for side in Side::BOTH {
+        headlights.queue(side).send(Control { target }.into()).await;
+    }
+}
+
+/// The relay has no display, so the passkey is flashed on both headlights one digit at a time
+/// (ten flashes for a zero), then their controls are restored.
+///
+/// Even a passkey of all zeros fits in the 30 s the central has to enter it.
+#[embassy_executor::task]
+pub async fn passkey_display_worker(ble: &'static BLE, headlights: &'static Headlights) {
+    loop {
+        let passkey = PASSKEY.wait().await;
+
+        // the defaults are conservative until the headlights have reported their config
+        let config = ble
+            .get_server()
+            .headlight
+            .config_get()
+            .ok()
+            .and_then(Config::deserialize)
+            .unwrap_or_default();
+        let flash_target = min(config.max_target_current, FLASH_TARGET);
+
+        set_both(headlights, 0).await;
+
+        for digit in passkey {
+            Timer::after(DIGIT_GAP).await;
+
+            let flashes = match digit - b'0' {
+                0 => 10,
+                n => n,
+            };
+
+            for _ in 0..flashes {
+                set_both(headlights, flash_target).await;
+                Timer::after(FLASH_ON).await;
+                set_both(headlights, 0).await;
+                Timer::after(FLASH_OFF).await;
+            }
+        }
+
+        // the flashes are not remembered, so this includes any control sent while flashing
+        for side in Side::BOTH {
+            let control = headlights
+                .control(side)
+                .unwrap_or_else(|| config.startup_control.clone());
+
+            headlights.queue(side).send(control.into()).await;
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use common::command::commands::{
+    deserialize_schedule, serialize_schedule, Schedule, SCHEDULE_SIZE,
+};
+use embassy_sync::{blocking_mutex::raw::ThreadModeRawMutex, mutex::Mutex};
+use embedded_storage_async::nor_flash::{NorFlash, ReadNorFlash};
+use heapless::Vec;
+use nrf_softdevice::{
+    ble::{Address, EncryptionInfo, IdentityKey, IdentityResolutionKey, MasterId},
+    raw, Flash, FlashError, Softdevice,
+};
+use static_cell::StaticCell;
+
+/// Flash shared by the persistent storage and the DFU staging area.
+pub type SharedFlash = Mutex<ThreadModeRawMutex, Flash>;
+
+static SHARED_FLASH: StaticCell<SharedFlash> = StaticCell::new();
+
+pub fn setup_flash(sd: &Softdevice) -> &'static SharedFlash {
+    SHARED_FLASH.init(Mutex::new(Flash::take(sd)))
+}
+
+/// Must match the `STORAGE` region in `memory.x`.
+const STORAGE_START: u32 = 0x000f_a000;
+const PAGE_SIZE: u32 = 4 * 1024;
+
+/// Each kind of data gets its own page, so it can be erased independently.
+///
+/// New pages are added below the others, so existing data stays in place.
+const SCHEDULE_START: u32 = STORAGE_START;
+const BONDS_START: u32 = STORAGE_START + PAGE_SIZE;
+const IDENTITY_START: u32 = STORAGE_START + 2 * PAGE_SIZE;
+
+pub const MAX_BONDS: usize = 4;
+
+/// Each bond occupies a fixed size, word aligned record.
+const RECORD_SIZE: usize = 64;
+/// Distinguishes a stored record from erased flash.
+const RECORD_VALID: u8 = 0xa5;
+
+pub type Bonds = Vec<Bond, MAX_BONDS>;
+
+#[derive(Clone, Copy)]
+pub struct Bond {
+    pub master_id: MasterId,
+    pub key: EncryptionInfo,
+    pub peer_id: IdentityKey,
+}
+
+impl Bond {
+    fn to_record(&self) -> [u8; RECORD_SIZE] {
+        let mut record = [0xff; RECORD_SIZE];
+
+        record[0] = RECORD_VALID;
+        record[1] = self.key.flags;
+        record[2..4].copy_from_slice(&self.master_id.ediv.to_le_bytes());
+        record[4..12].copy_from_slice(&self.master_id.rand);
+        record[12..28].copy_from_slice(&self.key.ltk);
+        record[28..44].copy_from_slice(&self.peer_id.irk.as_raw().irk);
+        record[44] = self.peer_id.addr.flags;
+        record[45..51].copy_from_slice(&self.peer_id.addr.bytes);
+
+        record
+    }
+
+    fn from_record(record: &[u8; RECORD_SIZE]) -> Option<Self> {
+        if record[0] != RECORD_VALID {
+            return None;
+        }
+
+        let mut master_id = MasterId::default();
+        master_id.ediv = u16::from_le_bytes([record[2], record[3]]);
+        master_id.rand.copy_from_slice(&record[4..12]);
+
+        let mut key = EncryptionInfo::default();
+        key.flags = record[1];
+        key.ltk.copy_from_slice(&record[12..28]);
+
+        let mut irk = raw::ble_gap_irk_t::default();
+        irk.irk.copy_from_slice(&record[28..44]);
+
+        let mut addr = Address {
+            flags: record[44],
+            bytes: [0; 6],
+        };
+        addr.bytes.copy_from_slice(&record[45..51]);
+
+        Some(Self {
+            master_id,
+            key,
+            peer_id: IdentityKey {
+                irk: IdentityResolutionKey::from_raw(irk),
+                addr,
+            },
+        })
+    }
+}
+
+pub async fn load_bonds(flash: &SharedFlash) -> Bonds {
+    let mut flash = flash.lock().await;
+    let mut bonds = Bonds::new();
+    let mut record = [0; RECORD_SIZE];
+
+    for i in 0..MAX_BONDS {
+        let offset = BONDS_START + (i * RECORD_SIZE) as u32;
+
+        if flash.read(offset, &mut record).await.is_err() {
+            break;
+        }
+
+        if let Some(bond) = Bond::from_record(&record) {
+            bonds.push(bond).ok();
+        }
+    }
+
+    bonds
+}
+
+/// Rewrite the whole bond table, bonding is rare enough that wear is not a concern.
+pub async fn store_bonds(flash: &SharedFlash, bonds: &Bonds) -> Result<(), FlashError> {
+    let mut flash = flash.lock().await;
+
+    flash.erase(BONDS_START, BONDS_START + PAGE_SIZE).await?;
+
+    for (i, bond) in bonds.iter().enumerate() {
+        let offset = BONDS_START + (i * RECORD_SIZE) as u32;
+
+        flash.write(offset, &bond.to_record()).await?;
+    }
+
+    Ok(())
+}
+
+pub const MAX_NAME_LEN: usize = 20;
+
+pub type Name = Vec<u8, MAX_NAME_LEN>;
+
+/// Name header and content, word aligned.
+const IDENTITY_RECORD_SIZE: usize = 24;
+
+/// How the relay presents itself to scanning centrals.
+#[derive(Clone)]
+pub struct Identity {
+    pub name: Name,
+    /// Whether part of the device address is appended to the name,
+    /// to tell apart relays sharing a name
+    pub suffix: bool,
+}
+
+impl Identity {
+    fn to_record(&self) -> [u8; IDENTITY_RECORD_SIZE] {
+        let mut record = [0xff; IDENTITY_RECORD_SIZE];
+
+        record[0] = RECORD_VALID;
+        record[1] = self.suffix as u8;
+        record[2] = self.name.len() as u8;
+        record[3..3 + self.name.len()].copy_from_slice(&self.name);
+
+        record
+    }
+
+    fn from_record(record: &[u8; IDENTITY_RECORD_SIZE]) -> Option<Self> {
+        if record[0] != RECORD_VALID {
+            return None;
+        }
+
+        let len = record[2] as usize;
+
+        Some(Self {
+            name: Vec::from_slice(record.get(3..3 + len)?).ok()?,
+            suffix: record[1] != 0,
+        })
+    }
+}
+
+pub async fn load_identity(flash: &SharedFlash) -> Option<Identity> {
+    let mut flash = flash.lock().await;
+    let mut record = [0; IDENTITY_RECORD_SIZE];
+
+    flash.read(IDENTITY_START, &mut record).await.ok()?;
+
+    Identity::from_record(&record)
+}
+
+pub async fn store_identity(flash: &SharedFlash, identity: &Identity) -> Result<(), FlashError> {
+    let mut flash = flash.lock().await;
+
+    flash
+        .erase(IDENTITY_START, IDENTITY_START + PAGE_SIZE)
+        .await?;
+    flash.write(IDENTITY_START, &identity.to_record()).await
+}
+
+/// Validity header and schedule, word aligned.
+const SCHEDULE_RECORD_SIZE: usize = (1 + SCHEDULE_SIZE + 3) / 4 * 4;
+
+pub async fn load_schedule(flash: &SharedFlash) -> Option<Schedule> {
+    let mut flash = flash.lock().await;
+    let mut record = [0; SCHEDULE_RECORD_SIZE];
+
+    flash.read(SCHEDULE_START, &mut record).await.ok()?;
+
+    if record[0] != RECORD_VALID {
+        return None;
+    }
+
+    deserialize_schedule(record[1..1 + SCHEDULE_SIZE].try_into().ok()?)
+}
+
+pub async fn store_schedule(flash: &SharedFlash, schedule: &Schedule) -> Result<(), FlashError> {
+    let mut record = [0xff; SCHEDULE_RECORD_SIZE];
+
+    record[0] = RECORD_VALID;
+    record[1..1 + SCHEDULE_SIZE].copy_from_slice(&serialize_schedule(schedule));
+
+    let mut flash = flash.lock().await;
+
+    flash
+        .erase(SCHEDULE_START, SCHEDULE_START + PAGE_SIZE)
+        .await?;
+    flash.write(SCHEDULE_START, &record).await
+}
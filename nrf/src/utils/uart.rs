@@ -1,45 +1,67 @@
 use embassy_nrf::{
     buffered_uarte::{Baudrate, BufferedUarte, BufferedUarteRx, BufferedUarteTx},
-    peripherals::{TIMER1, UARTE0},
+    peripherals::{TIMER1, TIMER2, UARTE0, UARTE1},
     uarte,
 };
 use static_cell::StaticCell;
 
-use crate::{Irqs, SerialResource};
+use crate::{Irqs, SerialLeftResource, SerialRightResource};
 
 pub const BUF_SIZE: usize = 64;
 
-static RX_BUF: StaticCell<[u8; BUF_SIZE]> = StaticCell::new();
-static TX_BUF: StaticCell<[u8; BUF_SIZE]> = StaticCell::new();
-
-static GLOBAL_UART: StaticCell<BufferedUarte<'static, UARTE0, TIMER1>> = StaticCell::new();
-
-pub fn setup_uart(
-    serial: SerialResource,
-    baudrate: Baudrate,
-) -> (
-    BufferedUarteRx<'static, 'static, UARTE0, TIMER1>,
-    BufferedUarteTx<'static, 'static, UARTE0, TIMER1>,
-) {
-    let mut uart_config = uarte::Config::default();
-    uart_config.baudrate = baudrate;
-
-    let rx_buf = RX_BUF.init([0; BUF_SIZE]);
-    let tx_buf = TX_BUF.init([0; BUF_SIZE]);
-
-    let uart = GLOBAL_UART.init(BufferedUarte::new(
-        serial.uart,
-        serial.timer,
-        serial.ppi0,
-        serial.ppi1,
-        serial.ppi_group,
-        Irqs,
-        serial.rx,
-        serial.tx,
-        uart_config,
-        rx_buf,
-        tx_buf,
-    ));
-
-    uart.split()
+pub type LeftRx = BufferedUarteRx<'static, 'static, UARTE0, TIMER1>;
+pub type LeftTx = BufferedUarteTx<'static, 'static, UARTE0, TIMER1>;
+pub type RightRx = BufferedUarteRx<'static, 'static, UARTE1, TIMER2>;
+pub type RightTx = BufferedUarteTx<'static, 'static, UARTE1, TIMER2>;
+
+/// Each link needs its own buffers and peripherals, so a setup function is generated per link.
+macro_rules! setup_uart {
+    ($name:ident, $resource:ty, $uart:ty, $timer:ty, $rx:ty, $tx:ty) => {
+        pub fn $name(serial: $resource, baudrate: Baudrate) -> ($rx, $tx) {
+            static RX_BUF: StaticCell<[u8; BUF_SIZE]> = StaticCell::new();
+            static TX_BUF: StaticCell<[u8; BUF_SIZE]> = StaticCell::new();
+
+            static GLOBAL_UART: StaticCell<BufferedUarte<'static, $uart, $timer>> =
+                StaticCell::new();
+
+            let mut uart_config = uarte::Config::default();
+            uart_config.baudrate = baudrate;
+
+            let rx_buf = RX_BUF.init([0; BUF_SIZE]);
+            let tx_buf = TX_BUF.init([0; BUF_SIZE]);
+
+            let uart = GLOBAL_UART.init(BufferedUarte::new(
+                serial.uart,
+                serial.timer,
+                serial.ppi0,
+                serial.ppi1,
+                serial.ppi_group,
+                Irqs,
+                serial.rx,
+                serial.tx,
+                uart_config,
+                rx_buf,
+                tx_buf,
+            ));
+
+            uart.split()
+        }
+    };
 }
+
+setup_uart!(
+    setup_left_uart,
+    SerialLeftResource,
+    UARTE0,
+    TIMER1,
+    LeftRx,
+    LeftTx
+);
+setup_uart!(
+    setup_right_uart,
+    SerialRightResource,
+    UARTE1,
+    TIMER2,
+    RightRx,
+    RightTx
+);
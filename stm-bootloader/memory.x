@@ -0,0 +1,20 @@
+MEMORY
+{
+  /* NOTE 1 K = 1 KiBi = 1024 bytes */
+  /* must match the application layout in ../stm/memory.x */
+  FLASH                             : ORIGIN = 0x08000000, LENGTH = 7K
+  BOOTLOADER_STATE                  : ORIGIN = 0x08001C00, LENGTH = 1K
+  ACTIVE                            : ORIGIN = 0x08002000, LENGTH = 11K
+  DFU                               : ORIGIN = 0x08004C00, LENGTH = 12K
+  /* the first 48 words and the last word are reserved for the application (see VECTORS and STANDBY) */
+  RAM                         (rwx) : ORIGIN = 0x200000C0, LENGTH = 4K - 192 - 4
+}
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(FLASH);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(FLASH);
+
+__bootloader_active_start = ORIGIN(ACTIVE) - ORIGIN(FLASH);
+__bootloader_active_end = ORIGIN(ACTIVE) + LENGTH(ACTIVE) - ORIGIN(FLASH);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(FLASH);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(FLASH);
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::{entry, exception};
+#[cfg(feature = "defmt")]
+use defmt_rtt as _;
+use embassy_boot_stm32::{BootLoader, BootLoaderConfig};
+use embassy_stm32::flash::{Flash, BANK1_REGION};
+use embassy_sync::blocking_mutex::Mutex;
+
+/// Swaps in a staged image (or reverts an unconfirmed one) and jumps to the application.
+///
+/// The application confirms an image once it is running, if it resets before doing so
+/// (including by its watchdog) the previous image is swapped back in.
+#[entry]
+fn main() -> ! {
+    let p = embassy_stm32::init(Default::default());
+
+    let layout = Flash::new_blocking(p.FLASH).into_blocking_regions();
+    let flash = Mutex::new(RefCell::new(layout.bank1_region));
+
+    let config = BootLoaderConfig::from_linkerfile_blocking(&flash);
+    let active_offset = config.active.offset();
+    // scratch buffer must hold one page
+    let bl = BootLoader::prepare::<_, _, _, 1024>(config);
+
+    unsafe { bl.load(BANK1_REGION.base + active_offset) }
+}
+
+#[exception]
+unsafe fn HardFault(_: &cortex_m_rt::ExceptionFrame) -> ! {
+    SCB::sys_reset()
+}
+
+#[exception]
+unsafe fn DefaultHandler(_irqn: i16) -> ! {
+    SCB::sys_reset()
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    SCB::sys_reset()
+}
@@ -1,5 +1,27 @@
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
+
 fn main() {
+    // the application is linked after the bootloader,
+    // so the layout cannot come from embassy-stm32's `memory-x`
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    File::create(out.join("memory.x"))
+        .unwrap()
+        .write_all(include_bytes!("memory.x"))
+        .unwrap();
+    File::create(out.join("size.x"))
+        .unwrap()
+        .write_all(include_bytes!("size.x"))
+        .unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed=size.x");
+
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
+    // after link.x, which defines the symbols it checks
+    println!("cargo:rustc-link-arg-bins=-Tsize.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
 }
@@ -0,0 +1,26 @@
+MEMORY
+{
+  /* NOTE 1 K = 1 KiBi = 1024 bytes */
+  /* STM32F031K6: 32K of flash in 1K pages, shared with the bootloader (see ../stm-bootloader) */
+  BOOTLOADER                        : ORIGIN = 0x08000000, LENGTH = 7K
+  BOOTLOADER_STATE                  : ORIGIN = 0x08001C00, LENGTH = 1K
+  FLASH                             : ORIGIN = 0x08002000, LENGTH = 11K
+  /* must be one page larger than FLASH for the swap */
+  DFU                               : ORIGIN = 0x08004C00, LENGTH = 12K
+  /* last page is reserved for the headlight configuration */
+  CONFIG                            : ORIGIN = 0x08007C00, LENGTH = 1K
+  /* copy of the vector table, mapped at address 0 since the M0 has no VTOR (48 words) */
+  VECTORS                     (rwx) : ORIGIN = 0x20000000, LENGTH = 192
+  RAM                         (rwx) : ORIGIN = 0x200000C0, LENGTH = 4K - 192 - 4
+  /* last word is left alone by the bootloader, so standby survives watchdog resets */
+  STANDBY                     (rw)  : ORIGIN = 0x20000FFC, LENGTH = 4
+}
+
+__vectors = ORIGIN(VECTORS);
+__standby = ORIGIN(STANDBY);
+
+__bootloader_state_start = ORIGIN(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
+__bootloader_state_end = ORIGIN(BOOTLOADER_STATE) + LENGTH(BOOTLOADER_STATE) - ORIGIN(BOOTLOADER);
+
+__bootloader_dfu_start = ORIGIN(DFU) - ORIGIN(BOOTLOADER);
+__bootloader_dfu_end = ORIGIN(DFU) + LENGTH(DFU) - ORIGIN(BOOTLOADER);
@@ -0,0 +1,9 @@
+/* Checked once link.x has placed every section, so an image that does not fit fails to link */
+
+/* embassy's tasks live in statics, but interrupts and the executors still need a stack */
+_min_stack_size = 512;
+
+ASSERT(__sidata + SIZEOF(.data) <= ORIGIN(FLASH) + LENGTH(FLASH),
+  "the application (.text, .rodata and .data) does not fit its flash partition, see memory.x");
+ASSERT(ORIGIN(RAM) + LENGTH(RAM) - __euninit >= _min_stack_size,
+  "static RAM (.data, .bss and .uninit) leaves less than _min_stack_size for the stack");
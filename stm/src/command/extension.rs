@@ -1,9 +1,15 @@
-use common::{command::commands::*, types::RuntimeError};
+use common::{
+    command::commands::*,
+    properties::PROPERTIES,
+    types::{FirmwareError, LogLevel, LogModule, RuntimeError},
+    utils::log::codes::{config, time, update},
+};
 use cortex_m::peripheral::SCB;
+use embassy_time::{Duration, Timer};
 
 use crate::{
     fmt::{error, info, warn},
-    utils::model::Model,
+    utils::{clock, log, model::Model},
 };
 #[cfg(feature = "defmt")]
 use defmt::Format;
@@ -11,20 +17,30 @@ use defmt::Format;
 #[cfg_attr(feature = "defmt", derive(Format))]
 pub enum Error {
     RequestUnavailable,
+    Firmware(FirmwareError),
 }
 
 pub trait Execute {
     async fn run(self, model: &Model) -> Result<(), Error>;
 }
 
+/// Record a failed firmware update step.
+fn log_firmware(result: Result<(), FirmwareError>) {
+    if let Err(e) = result {
+        log::record(
+            LogLevel::Error,
+            LogModule::Update,
+            update::FAILED,
+            e as u32,
+            0,
+        );
+    }
+}
+
 impl Execute for Request {
     async fn run(self, model: &Model) -> Result<(), Error> {
         let bundle = match self {
-            Request::Status => Status {
-                mode: model.get_mode().await,
-                error: model.get_error().await,
-            }
-            .into(),
+            Request::Status => model.get_status().await.into(),
             Request::Control => model.get_control().await.into(),
             Request::Monitor => model
                 .get_monitor_immediately()
@@ -32,6 +48,19 @@ impl Execute for Request {
                 .ok_or(Error::RequestUnavailable)?
                 .into(),
             Request::Config => model.config.clone().into(),
+            Request::Properties => PROPERTIES.into(),
+            Request::Derating => model
+                .get_derating_immediately()
+                .await
+                .ok_or(Error::RequestUnavailable)?
+                .into(),
+            Request::Log => {
+                for entry in log::entries() {
+                    model.send_queue.send(entry.into()).await;
+                }
+
+                return Ok(());
+            }
         };
 
         model.send_queue.send(bundle).await;
@@ -58,17 +87,29 @@ impl Execute for Config {
                 match lock.write_config(valid_config) {
                     Ok(_) => {
                         info!("Config write complete, resetting!");
+                        log::record(LogLevel::Info, LogModule::Config, config::WRITTEN, 0, 0);
+                        // give the log entry time to be sent
+                        Timer::after(Duration::from_millis(100)).await;
                         SCB::sys_reset()
                     }
                     Err(e) => {
-                        model.set_error(RuntimeError::Flash.into(), true).await;
+                        log::record(
+                            LogLevel::Error,
+                            LogModule::Config,
+                            config::WRITE_FAILED,
+                            0,
+                            0,
+                        );
+                        model.raise_faults([RuntimeError::Flash.into()], true).await;
                         error!("Failed to write config with error: {}.", e);
                     }
                 }
             }
-            Err(e) => {
-                model.set_error(e.into(), true).await;
-                warn!("Received config was invalid for reason: {}.", e);
+            Err(errors) => {
+                model
+                    .raise_faults(errors.iter().map(|&e| e.into()), true)
+                    .await;
+                warn!("Received config was invalid for reasons: {}.", errors);
             }
         }
 
@@ -76,11 +117,97 @@ impl Execute for Config {
     }
 }
 
+impl Execute for ClearFault {
+    async fn run(self, model: &Model) -> Result<(), Error> {
+        model.clear_faults(self.faults, true).await;
+        Ok(())
+    }
+}
+
 impl Execute for Reset {
     async fn run(self, model: &Model) -> Result<(), Error> {
         match self {
             Self::Now => SCB::sys_reset(),
             Self::Factory => Config::default().run(model).await,
+            Self::Standby => model.standby().await,
+        }
+    }
+}
+
+impl Execute for FirmwareBegin {
+    async fn run(self, model: &Model) -> Result<(), Error> {
+        // the half-bridge must stay disabled while flash is being rewritten
+        if model.config.enabled {
+            model.shutdown_regulation().await;
+        }
+
+        let size = self.size;
+        let mut lock = model.updater.lock().await;
+        let result = lock.begin(self);
+        model.send_queue.send(lock.status(result).into()).await;
+
+        if result.is_ok() {
+            log::record(LogLevel::Info, LogModule::Update, update::BEGUN, size, 0);
+        }
+        log_firmware(result);
+
+        result.map_err(Error::Firmware)
+    }
+}
+
+impl Execute for FirmwareChunk {
+    async fn run(self, model: &Model) -> Result<(), Error> {
+        let mut lock = model.updater.lock().await;
+        let result = lock.write(self);
+        model.send_queue.send(lock.status(result).into()).await;
+        log_firmware(result);
+
+        result.map_err(Error::Firmware)
+    }
+}
+
+impl Execute for FirmwareCommit {
+    async fn run(self, model: &Model) -> Result<(), Error> {
+        let mut lock = model.updater.lock().await;
+
+        match self {
+            Self::Apply => {
+                let result = lock.apply();
+                model.send_queue.send(lock.status(result).into()).await;
+                log_firmware(result);
+                result.map_err(Error::Firmware)?;
+
+                info!("Firmware update staged, resetting!");
+                log::record(LogLevel::Info, LogModule::Update, update::COMMITTED, 0, 0);
+                // give the acknowledgement and log entry time to be sent
+                Timer::after(Duration::from_millis(100)).await;
+                SCB::sys_reset()
+            }
+            Self::Abort => {
+                lock.abort();
+                model.send_queue.send(lock.status(Ok(())).into()).await;
+
+                Ok(())
+            }
         }
     }
 }
+
+impl Execute for Heartbeat {
+    async fn run(self, model: &Model) -> Result<(), Error> {
+        // echoed unchanged so the relay knows the link is alive
+        model.send_queue.send(self.into()).await;
+
+        Ok(())
+    }
+}
+
+impl Execute for TimeSync {
+    async fn run(self, _model: &Model) -> Result<(), Error> {
+        clock::sync_time(&self);
+        info!("Time synced to {}.", self.unix);
+        log::record(LogLevel::Info, LogModule::Time, time::SYNCED, self.unix, 0);
+
+        Ok(())
+    }
+}
@@ -10,19 +10,27 @@ use cortex_m::peripheral::SCB;
 use cortex_m_rt::entry;
 #[cfg(not(feature = "defmt"))]
 use cortex_m_rt::exception;
-use embassy_stm32::flash::Flash;
 use fmt::info;
 #[cfg(not(feature = "defmt"))]
 use panic_halt as _;
 use static_cell::StaticCell;
 use utils::{
-    adc::setup_adc,
+    adc::{setup_adc, SharedAdc},
+    ambient::ambient_worker,
     config::Configurator,
+    flash::setup_flash,
     hb::setup_hb,
+    input::{button_worker, setup_input, switch_worker},
+    log::log_worker,
     model::{model_worker, Model},
     regulation::{regulation_worker, Regulator, RegulatorHardware, RegulatorProxy},
+    signal::{setup_signals, signal_worker},
+    standby,
     status::setup_status,
     uart::setup_uart,
+    update::Updater,
+    vectors::relocate_vector_table,
+    watchdog::{setup_watchdog, watchdog_worker},
 };
 #[cfg(feature = "defmt")]
 use {defmt_rtt as _, panic_probe as _};
@@ -30,18 +38,20 @@ use {defmt_rtt as _, panic_probe as _};
 use common::{
     assign_resources,
     command::{commands::*, reader::HeadlightCommandReader, writer::HeadlightCommandWriter},
-    types::Mode,
+    types::Protocol,
 };
 use embassy_executor::{Executor, InterruptExecutor};
 use embassy_stm32::{
     adc, bind_interrupts,
+    gpio::{Input, Pull},
     interrupt::{InterruptExt, Priority},
-    peripherals::{self, ADC, PA12, PA6, TIM1, USART1},
+    peripherals::{self, ADC, PA0, PA1, PA11, PA12, PA15, PA6, PB3, PB4, TIM1, USART1},
     rcc,
     time::mhz,
     Config as PeripheralConfig,
 };
 use embassy_stm32::{interrupt, usart};
+use embassy_sync::mutex::Mutex;
 
 mod command;
 mod fmt;
@@ -66,6 +76,9 @@ assign_resources! {
         cur_sense: PA2,
         temp: PA5
     }
+    pub ambient: AmbientResources {
+        sensor: PA4
+    }
     pub hb: HalfBridgeResources {
         timer: TIM1 = PWMTimer,
         control: PA8,
@@ -77,6 +90,22 @@ assign_resources! {
         rx: PA10,
         tx: PA9
     }
+    pub input: InputResources {
+        button: PA0 = ButtonPin,
+        button_exti: EXTI0,
+        switch: PA1 = SwitchPin,
+        switch_exti: EXTI1
+    }
+    pub signals: SignalResources {
+        turn_left: PA11 = TurnLeftPin,
+        turn_left_exti: EXTI11,
+        turn_right: PA15 = TurnRightPin,
+        turn_right_exti: EXTI15,
+        brake: PB3 = BrakePin,
+        brake_exti: EXTI3,
+        ignition: PB4 = IgnitionPin,
+        ignition_exti: EXTI4
+    }
 }
 
 static PRIORITY_EXECUTOR: InterruptExecutor = InterruptExecutor::new();
@@ -84,6 +113,7 @@ static NORMAL_EXECUTOR: StaticCell<Executor> = StaticCell::new();
 
 static REG_PROXY: RegulatorProxy = RegulatorProxy::new();
 static MODEL: StaticCell<Model> = StaticCell::new();
+static SHARED_ADC: StaticCell<SharedAdc> = StaticCell::new();
 
 #[interrupt]
 unsafe fn I2C1() {
@@ -92,6 +122,9 @@ unsafe fn I2C1() {
 
 #[entry]
 fn main() -> ! {
+    // before anything can raise an interrupt
+    relocate_vector_table();
+
     // interrupts
     interrupt::I2C1.set_priority(Priority::P1);
     interrupt::USART1.set_priority(Priority::P2);
@@ -106,24 +139,34 @@ fn main() -> ! {
 
     // distribute peripherals
     let p = embassy_stm32::init(peripheral_config);
-    let r = split_resources!(p);
+    let mut r = split_resources!(p);
 
     // setup minimal peripherals
-    let flash = Flash::new_blocking(p.FLASH);
+    let watchdog = setup_watchdog(p.IWDG);
+    let flash = setup_flash(p.FLASH);
     let mut fault = setup_status(r.status);
 
-    // read config from flash and any error that occured while doing so
+    // setup physical inputs (which are also wake sources)
+    let (button, switch) = setup_input(r.input);
+    let signals = setup_signals(r.signals);
+
+    if standby::resuming() {
+        // the watchdog woke the headlight, not a wake source, the RX line must not float
+        let _rx = Input::new(&mut r.serial.rx, Pull::Up);
+        standby::enter();
+    }
+
+    // read config from flash and any errors that occured while doing so
     let mut configurator = Configurator::new(flash);
-    let (headlight_config, maybe_error) = configurator.load_config(&mut fault);
+    let mut status = Status::default();
+    let headlight_config = configurator.load_config(&mut fault, &mut status);
 
     // initialize device model
     let model = MODEL.init(Model::new(
         headlight_config,
         configurator,
-        maybe_error.map_or(Status::default(), |error| Status {
-            mode: Mode::default(),
-            error,
-        }),
+        Updater::new(flash),
+        status,
         &REG_PROXY,
     ));
 
@@ -132,13 +175,17 @@ fn main() -> ! {
     // setup comms peripherals
     let (tx, rx) = setup_uart(r.serial, 9600);
 
-    // setup command reader/writer
-    let reader = HeadlightCommandReader::new(rx);
-    let writer = HeadlightCommandWriter::new(tx);
+    // setup command reader/writer, addressed if the headlight shares a bus
+    let protocol = Protocol::headlight(model.config.address);
+    let reader = HeadlightCommandReader::new(rx).with_protocol(protocol);
+    let writer = HeadlightCommandWriter::with_protocol(tx, protocol);
+
+    // the ADC is shared, the ambient light is sampled even while not regulating
+    let (adc, vref) = setup_adc(p.ADC);
+    let adc = SHARED_ADC.init(Mutex::new(adc));
 
     if model.config.enabled {
         // setup regulation peripherals
-        let (adc, vref) = setup_adc(p.ADC);
         let (pwm, enable) = setup_hb(r.hb, model.config.pwm_freq);
 
         let regulator = Regulator::new(
@@ -163,8 +210,14 @@ fn main() -> ! {
     // start low priority executor for comms
     let normal_executor = NORMAL_EXECUTOR.init(Executor::new());
     normal_executor.run(|spawner| {
+        spawner.must_spawn(watchdog_worker(watchdog));
         spawner.must_spawn(model_worker(model));
         spawner.must_spawn(receive_command_worker(reader, model));
         spawner.must_spawn(send_command_worker(writer, &model.send_queue));
+        spawner.must_spawn(log_worker(&model.send_queue));
+        spawner.must_spawn(button_worker(button, model));
+        spawner.must_spawn(switch_worker(switch, model));
+        spawner.must_spawn(signal_worker(signals, model));
+        spawner.must_spawn(ambient_worker(model, adc, r.ambient));
     });
 }
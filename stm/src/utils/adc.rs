@@ -1,11 +1,16 @@
+use common::properties::PROPERTIES;
 use embassy_stm32::{
     adc::{Adc, Resolution, SampleTime, Vref},
     peripherals::ADC,
 };
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
 use embassy_time::Delay;
 
 use crate::Irqs;
 
+/// Shared by the regulator and the ambient light sensor, which is sampled even while not regulating.
+pub type SharedAdc = Mutex<CriticalSectionRawMutex, Adc<'static, ADC>>;
+
 pub fn sample_to_mv(sample: u16, vref: u16) -> Option<u16> {
     // From https://www.st.com/resource/en/datasheet/stm32f031c6.pdf
     // 6.3.4 Embedded reference voltage
@@ -21,13 +26,11 @@ pub fn sample_to_mv(sample: u16, vref: u16) -> Option<u16> {
 }
 
 pub fn mv_to_ma(mv: u16) -> Option<u16> {
-    const GAIN: u16 = 10;
-    const R_SHUNT: u16 = 33;
-
-    mv.checked_mul(GAIN)?.checked_div(R_SHUNT)
+    mv.checked_mul(PROPERTIES.sense_gain)?
+        .checked_div(PROPERTIES.r_shunt)
 }
 
-pub fn setup_adc<'a>(hw_adc: ADC) -> (Adc<'a, ADC>, Vref) {
+pub fn setup_adc(hw_adc: ADC) -> (Adc<'static, ADC>, Vref) {
     let mut adc = Adc::new(hw_adc, Irqs, &mut Delay);
     adc.set_resolution(Resolution::TwelveBit);
     adc.set_sample_time(SampleTime::Cycles13_5);
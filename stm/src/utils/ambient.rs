@@ -0,0 +1,118 @@
+use common::{
+    command::commands::{AmbientEvent, Control},
+    types::{AmbientLight, LogLevel, LogModule},
+    utils::log::codes::ambient as ambient_codes,
+};
+use embassy_time::{Duration, Ticker, Timer};
+
+use crate::{
+    fmt::info,
+    utils::{adc::SharedAdc, log, model::Model},
+    AmbientResources,
+};
+
+/// Interval between steps while fading between targets.
+const FADE_STEP: Duration = Duration::from_millis(50);
+/// Ambient light changes slowly, so it is only sampled this often.
+const SAMPLE_PERIOD: Duration = Duration::from_secs(1);
+
+/// The light after seeing `level`, only changing once a threshold is crossed.
+fn judge(
+    light: Option<AmbientLight>,
+    level: u16,
+    dusk_level: u16,
+    dawn_level: u16,
+) -> AmbientLight {
+    match light {
+        Some(AmbientLight::Day) if level <= dusk_level => AmbientLight::Night,
+        Some(AmbientLight::Night) if level >= dawn_level => AmbientLight::Day,
+        Some(light) => light,
+        // at boot anything short of day is night, to err on the side of being seen
+        None if level < dawn_level => AmbientLight::Night,
+        None => AmbientLight::Day,
+    }
+}
+
+/// Fade linearly from the current target to `target`.
+///
+/// Any other change to the control (the app or an input) takes over and ends the fade.
+async fn fade(model: &Model, target: u16, transition: Duration) {
+    let start = model.get_control().await.target;
+    let steps = (transition.as_ticks() / FADE_STEP.as_ticks()).max(1) as i32;
+    let mut expected = start;
+
+    for step in 1..=steps {
+        if model.get_control().await.target != expected {
+            info!("Fade was overridden.");
+            return;
+        }
+
+        let delta = (i32::from(target) - i32::from(start)) * step / steps;
+        expected = (i32::from(start) + delta) as u16;
+
+        // only the final target is notified
+        model
+            .set_control(Control { target: expected }, step == steps)
+            .await;
+
+        if step != steps {
+            Timer::after(FADE_STEP).await;
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn ambient_worker(
+    model: &'static Model,
+    adc: &'static SharedAdc,
+    mut sensor: AmbientResources,
+) {
+    let ambient = &model.config.ambient;
+
+    if !ambient.auto {
+        return;
+    }
+
+    let mut light = None;
+    let mut ticker = Ticker::every(SAMPLE_PERIOD);
+
+    loop {
+        ticker.next().await;
+
+        // the sensor is a voltage divider from VDD, so the sample is left raw
+        let level = adc.lock().await.read(&mut sensor.sensor).await;
+        let new = judge(light, level, ambient.dusk_level, ambient.dawn_level);
+
+        if light == Some(new) {
+            continue;
+        }
+
+        info!("Ambient light is now {} at level {}.", new, level);
+        log::record(
+            LogLevel::Info,
+            LogModule::Ambient,
+            ambient_codes::CHANGED,
+            new as u32,
+            level.into(),
+        );
+
+        light = Some(new);
+
+        model
+            .send_queue
+            .send(AmbientEvent { light: new, level }.into())
+            .await;
+
+        let target = match new {
+            AmbientLight::Day => ambient.day_target,
+            AmbientLight::Night => ambient.night_target,
+        };
+
+        fade(
+            model,
+            target,
+            Duration::from_millis(ambient.transition.into()),
+        )
+        .await;
+    }
+}
@@ -0,0 +1,21 @@
+use common::{command::commands::TimeSync, utils::clock::WallClock};
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Instant;
+
+/// Wall-clock time synced by the relay, lost whenever the headlight resets.
+static CLOCK: Mutex<CriticalSectionRawMutex, RefCell<WallClock>> =
+    Mutex::new(RefCell::new(WallClock::new()));
+
+pub fn sync_time(time: &TimeSync) {
+    let uptime = Instant::now().as_millis();
+
+    CLOCK.lock(|clock| clock.borrow_mut().sync(time, uptime));
+}
+
+/// The current Unix time in seconds, or 0 if the relay has not synced it since boot.
+pub fn timestamp() -> u32 {
+    let uptime = Instant::now().as_millis();
+
+    CLOCK.lock(|clock| clock.borrow().timestamp(uptime))
+}
@@ -1,10 +1,13 @@
 use common::{
-    command::commands::*, properties::PROPERTIES, types::*, utils::thermistor::celsius_to_sample,
+    command::commands::*,
+    properties::PROPERTIES,
+    types::*,
+    utils::validation::{validate_config, ConfigErrors},
 };
 #[cfg(feature = "defmt")]
 use defmt::Format;
 use embassy_stm32::{
-    flash::{Blocking, Error as FlashError, Flash, WRITE_SIZE},
+    flash::{Error as FlashError, WRITE_SIZE},
     gpio::Output,
 };
 use tiny_serde::{prelude::*, Deserialize, Serialize};
@@ -14,6 +17,9 @@ use crate::{
     FaultLEDPin,
 };
 
+use super::flash::SharedFlash;
+
+// must match the `CONFIG` region in memory.x
 const CONFIG_SECTOR: u32 = 31;
 const KIBBI: u32 = 1024;
 
@@ -21,7 +27,7 @@ const KIBBI: u32 = 1024;
 pub enum Error {
     Flash(FlashError),
     Deserialize,
-    Validation(ConfigError),
+    Validation(ConfigErrors),
 }
 
 impl From<FlashError> for Error {
@@ -30,8 +36,8 @@ impl From<FlashError> for Error {
     }
 }
 
-impl From<ConfigError> for Error {
-    fn from(value: ConfigError) -> Self {
+impl From<ConfigErrors> for Error {
+    fn from(value: ConfigErrors) -> Self {
         Self::Validation(value)
     }
 }
@@ -43,29 +49,15 @@ pub struct ValidatedConfig {
 }
 
 impl TryFrom<Config> for ValidatedConfig {
-    type Error = ConfigError;
+    type Error = ConfigErrors;
     fn try_from(config: Config) -> Result<Self, Self::Error> {
-        (PROPERTIES.min_pwm_freq..=PROPERTIES.max_pwm_freq)
-            .contains(&config.pwm_freq)
-            .then_some(())
-            .ok_or(ConfigError::PWMFreq)?;
-
-        (config.abs_max_load_current < PROPERTIES.abs_max_ma)
-            .then_some(())
-            .ok_or(ConfigError::MaxTarget)?;
-
-        (config.startup_control.target <= config.max_target_current)
-            .then_some(())
-            .ok_or(ConfigError::StartupTarget)?;
+        let errors = validate_config(&config, &PROPERTIES);
 
-        (config.gain >= 1).then_some(()).ok_or(ConfigError::Gain)?;
-
-        (config.throttle_start < config.throttle_stop
-            && celsius_to_sample(config.throttle_stop) < PROPERTIES.abs_max_temp)
-            .then_some(())
-            .ok_or(ConfigError::ThrottleBounds)?;
-
-        Ok(Self { inner: config })
+        if errors.is_empty() {
+            Ok(Self { inner: config })
+        } else {
+            Err(errors)
+        }
     }
 }
 
@@ -75,19 +67,20 @@ impl ValidatedConfig {
     }
 }
 
-pub struct Configurator<'a> {
-    flash: Flash<'a, Blocking>,
+pub struct Configurator {
+    flash: &'static SharedFlash,
 }
 
-impl<'a> Configurator<'a> {
-    pub const fn new(flash: Flash<'a, Blocking>) -> Self {
+impl Configurator {
+    pub const fn new(flash: &'static SharedFlash) -> Self {
         Self { flash }
     }
 
     fn read_config(&mut self) -> Result<Config, Error> {
         const CFG_SIZE: usize = <Config as _TinyDeSized>::SIZE;
         let mut buf = [0u8; CFG_SIZE];
-        self.flash.read(CONFIG_SECTOR * KIBBI, &mut buf)?;
+        self.flash
+            .lock(|flash| flash.borrow_mut().read(CONFIG_SECTOR * KIBBI, &mut buf))?;
         trace!("Read config buffer from flash: {}.", buf);
 
         Config::deserialize(unwrap!(buf[..CFG_SIZE].try_into())).ok_or(Error::Deserialize)
@@ -97,61 +90,62 @@ impl<'a> Configurator<'a> {
         const CFG_SIZE: usize = <Config as _TinySerSized>::SIZE;
         let mut buf = [0u8; CFG_SIZE + (WRITE_SIZE - CFG_SIZE % WRITE_SIZE)];
         buf[..CFG_SIZE].copy_from_slice(&config.inner().serialize());
-        self.flash
-            .blocking_erase(CONFIG_SECTOR * KIBBI, CONFIG_SECTOR * KIBBI + KIBBI)?;
-        self.flash.blocking_write(CONFIG_SECTOR * KIBBI, &buf)?;
+        self.flash.lock(|flash| {
+            let mut flash = flash.borrow_mut();
+            flash.blocking_erase(CONFIG_SECTOR * KIBBI, CONFIG_SECTOR * KIBBI + KIBBI)?;
+            flash.blocking_write(CONFIG_SECTOR * KIBBI, &buf)
+        })?;
 
         Ok(())
     }
 
     /// Loads the configuration from flash.
     ///
-    /// If an error occurs with flash or validation, the default configuration is returned.
+    /// If an error occurs with flash or validation, the default configuration is returned
+    /// and every error is raised on the provided status.
     ///
     /// # Panics
     /// Panics and raises the status LED if the default configuration is invalid.
     pub fn load_config<'b>(
         &mut self,
         fault: &mut Output<'b, FaultLEDPin>,
-    ) -> (ValidatedConfig, Option<HeadlightError>) {
-        let mut error = None;
-
+        status: &mut Status,
+    ) -> ValidatedConfig {
         let maybe_config = match self.read_config() {
             Ok(config) => match config.try_into() {
                 Ok(valid_config) => {
                     info!("Stored configuration is valid.");
                     Ok(valid_config)
                 }
-                Err(e) => {
+                Err(errors) => {
                     warn!(
-                        "Stored configuration is invalid for reason: {}. Loading default.",
-                        e
+                        "Stored configuration is invalid for reasons: {}. Loading default.",
+                        errors
                     );
 
-                    error = Some(HeadlightError::Config { e });
+                    for &e in errors.iter() {
+                        status.raise(e.into());
+                    }
 
                     Config::default().try_into()
                 }
             },
             Err(e) => {
                 warn!("Failed to read config from flash with error: {}.", e);
-                error = Some(RuntimeError::Flash.into());
+                status.raise(RuntimeError::Flash.into());
                 Config::default().try_into()
             }
         };
 
-        (
-            // by this point if the config is not present,
-            // the default must have been invalid
-            match maybe_config {
-                Ok(valid_config) => valid_config,
-                Err(e) => {
-                    fault.set_high();
-                    error!("Failed to load default configuration with error: {}.", e);
-                    panic!();
-                }
-            },
-            error,
-        )
+        // by this point if the config is not present,
+        // the default must have been invalid
+        match maybe_config {
+            Ok(valid_config) => valid_config,
+            Err(e) => {
+                fault.set_high();
+                error!("Failed to load default configuration with error: {}.", e);
+                panic!();
+            }
+        }
     }
 }
@@ -0,0 +1,18 @@
+use core::cell::RefCell;
+use embassy_stm32::{
+    flash::{Blocking, Flash},
+    peripherals::FLASH,
+};
+use embassy_sync::blocking_mutex::{raw::NoopRawMutex, Mutex};
+use static_cell::StaticCell;
+
+/// Flash shared by the configurator and the firmware updater.
+///
+/// Both only ever run on the normal executor, so no locking is necessary.
+pub type SharedFlash = Mutex<NoopRawMutex, RefCell<Flash<'static, Blocking>>>;
+
+static SHARED_FLASH: StaticCell<SharedFlash> = StaticCell::new();
+
+pub fn setup_flash(flash: FLASH) -> &'static SharedFlash {
+    SHARED_FLASH.init(Mutex::new(RefCell::new(Flash::new_blocking(flash))))
+}
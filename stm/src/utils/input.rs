@@ -0,0 +1,219 @@
+use common::{
+    command::commands::{Control, InputEvent},
+    types::{Gesture, InputAction, LogLevel, LogModule},
+    utils::log::codes::input as input_codes,
+};
+use embassy_futures::select::{select, Either};
+use embassy_stm32::{
+    exti::ExtiInput,
+    gpio::{Input, Pull},
+};
+use embassy_time::{with_timeout, Duration, Timer};
+
+use crate::{
+    fmt::info,
+    utils::{log, model::Model},
+    ButtonPin, InputResources, SwitchPin,
+};
+
+/// Contacts are considered settled once they have held a level for this long.
+const DEBOUNCE: Duration = Duration::from_millis(20);
+/// A press held for this long is a long press.
+const LONG_PRESS: Duration = Duration::from_millis(600);
+/// A second press within this long of the first release is a double press.
+const DOUBLE_PRESS_GAP: Duration = Duration::from_millis(300);
+/// How long the high beam is flashed for a short or double press.
+const FLASH_DURATION: Duration = Duration::from_millis(500);
+
+pub type Button = ExtiInput<'static, ButtonPin>;
+pub type Switch = ExtiInput<'static, SwitchPin>;
+
+/// Both inputs close to ground, so they are pulled up and active low.
+pub fn setup_input(r: InputResources) -> (Button, Switch) {
+    (
+        ExtiInput::new(Input::new(r.button, Pull::Up), r.button_exti),
+        ExtiInput::new(Input::new(r.switch, Pull::Up), r.switch_exti),
+    )
+}
+
+async fn pressed(button: &mut Button) {
+    loop {
+        button.wait_for_low().await;
+        Timer::after(DEBOUNCE).await;
+
+        if button.is_low() {
+            break;
+        }
+    }
+}
+
+async fn released(button: &mut Button) {
+    loop {
+        button.wait_for_high().await;
+        Timer::after(DEBOUNCE).await;
+
+        if button.is_high() {
+            break;
+        }
+    }
+}
+
+/// Wait for the next gesture, a long press is recognized while the button is still held.
+async fn gesture(button: &mut Button) -> Gesture {
+    pressed(button).await;
+
+    if let Either::Second(_) = select(released(button), Timer::after(LONG_PRESS)).await {
+        return Gesture::LongPress;
+    }
+
+    match with_timeout(DOUBLE_PRESS_GAP, pressed(button)).await {
+        Ok(_) => {
+            released(button).await;
+            Gesture::DoublePress
+        }
+        Err(_) => Gesture::ShortPress,
+    }
+}
+
+/// The preset after `target`, wrapping around to the first.
+fn next_preset(presets: [u16; 3], target: u16) -> u16 {
+    presets
+        .into_iter()
+        .find(|&preset| preset > target)
+        .unwrap_or(presets[0])
+}
+
+#[embassy_executor::task]
+pub async fn button_worker(mut button: Button, model: &'static Model) -> ! {
+    let input = &model.config.input;
+    // restored when toggled back on
+    let mut on_target = input.presets.low;
+
+    loop {
+        let gesture = gesture(&mut button).await;
+        let action = match gesture {
+            Gesture::ShortPress => input.short_press,
+            Gesture::LongPress => input.long_press,
+            Gesture::DoublePress => input.double_press,
+            // only produced by the switch
+            Gesture::HighBeam | Gesture::LowBeam => InputAction::None,
+        };
+
+        info!("Button {} performs {}.", gesture, action);
+        log::record(
+            LogLevel::Info,
+            LogModule::Input,
+            input_codes::GESTURE,
+            gesture as u32,
+            action as u32,
+        );
+
+        model
+            .send_queue
+            .send(InputEvent { gesture, action }.into())
+            .await;
+
+        let control = model.get_control().await;
+
+        match action {
+            InputAction::None => {}
+            InputAction::Toggle => {
+                let target = if control.target == 0 {
+                    on_target
+                } else {
+                    on_target = control.target;
+                    0
+                };
+
+                model.set_control(Control { target }, true).await;
+            }
+            InputAction::CyclePreset => {
+                let target = next_preset(input.presets.targets(), control.target);
+
+                model.set_control(Control { target }, true).await;
+            }
+            InputAction::FlashToPass => {
+                model
+                    .set_control(
+                        Control {
+                            target: input.high_beam,
+                        },
+                        true,
+                    )
+                    .await;
+
+                // a long press flashes for as long as it is held
+                if let Gesture::LongPress = gesture {
+                    released(&mut button).await;
+                } else {
+                    Timer::after(FLASH_DURATION).await;
+                }
+
+                model.set_control(control, true).await;
+            }
+        }
+
+        // a long press must end before the next gesture can begin
+        if button.is_low() {
+            released(&mut button).await;
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub async fn switch_worker(mut switch: Switch, model: &'static Model) -> ! {
+    let mut high = false;
+    // restored when switched back to low
+    let mut low_control = model.get_control().await;
+
+    loop {
+        // the switch closes to ground in the high position,
+        // checked before waiting so the position at boot is applied too
+        if switch.is_low() == high {
+            switch.wait_for_any_edge().await;
+            Timer::after(DEBOUNCE).await;
+            continue;
+        }
+
+        high = !high;
+
+        let gesture = if high {
+            low_control = model.get_control().await;
+
+            model
+                .set_control(
+                    Control {
+                        target: model.config.input.high_beam,
+                    },
+                    true,
+                )
+                .await;
+
+            Gesture::HighBeam
+        } else {
+            model.set_control(low_control.clone(), true).await;
+
+            Gesture::LowBeam
+        };
+
+        info!("Switch moved to {}.", gesture);
+        log::record(
+            LogLevel::Info,
+            LogModule::Input,
+            input_codes::GESTURE,
+            gesture as u32,
+            InputAction::None as u32,
+        );
+
+        model
+            .send_queue
+            .send(
+                InputEvent {
+                    gesture,
+                    action: InputAction::None,
+                }
+                .into(),
+            )
+            .await;
+    }
+}
@@ -0,0 +1,54 @@
+use common::{
+    command::commands::Log,
+    types::{LogLevel, LogModule, LogSource},
+    utils::log::LogRing,
+};
+use core::cell::RefCell;
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    channel::Channel,
+};
+use embassy_time::Instant;
+use heapless::Vec;
+
+use crate::{command::writer::WriterQueue, utils::clock::timestamp};
+
+/// RAM is scarce, the relay keeps a longer history.
+const LOG_SIZE: usize = 8;
+
+static LOG: Mutex<CriticalSectionRawMutex, RefCell<LogRing<LOG_SIZE>>> =
+    Mutex::new(RefCell::new(LogRing::new()));
+
+/// Entries waiting to be forwarded to the relay.
+static FORWARD: Channel<CriticalSectionRawMutex, Log, 4> = Channel::new();
+
+/// Record a log entry, safe to call from either executor.
+pub fn record(level: LogLevel, module: LogModule, code: u8, arg0: u32, arg1: u32) {
+    let entry = Log {
+        time: timestamp(),
+        uptime: Instant::now().as_millis() as u32,
+        source: LogSource::Headlight,
+        level,
+        module,
+        code,
+        arg0,
+        arg1,
+    };
+
+    LOG.lock(|log| log.borrow_mut().push(entry));
+    // if the link cannot keep up, the entry can still be requested from the ring
+    FORWARD.try_send(entry).ok();
+}
+
+/// Every buffered entry, oldest first.
+pub fn entries() -> Vec<Log, LOG_SIZE> {
+    LOG.lock(|log| log.borrow().iter().copied().collect())
+}
+
+#[embassy_executor::task]
+pub async fn log_worker(queue: &'static WriterQueue) -> ! {
+    loop {
+        let entry = FORWARD.receive().await;
+        queue.send(entry.into()).await;
+    }
+}
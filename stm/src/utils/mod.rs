@@ -1,7 +1,17 @@
 pub mod adc;
+pub mod ambient;
+pub mod clock;
 pub mod config;
+pub mod flash;
 pub mod hb;
+pub mod input;
+pub mod log;
 pub mod model;
 pub mod regulation;
+pub mod signal;
+pub mod standby;
 pub mod status;
 pub mod uart;
+pub mod update;
+pub mod vectors;
+pub mod watchdog;
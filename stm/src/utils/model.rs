@@ -1,11 +1,28 @@
-use common::{command::commands::*, types::*};
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use common::{
+    command::commands::*,
+    properties::PROPERTIES,
+    types::*,
+    utils::log::codes::{fault, standby as standby_codes},
+};
+use core::cell::Cell;
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex as BlockingMutex},
+    mutex::Mutex,
+};
+use embassy_time::{Duration, Timer};
 
 use crate::command::writer::WriterQueue;
 
+use crate::fmt::{error, info};
+
 use super::{
+    clock::timestamp,
     config::{Configurator, ValidatedConfig},
+    log,
     regulation::RegulatorProxy,
+    signal::ActiveSignals,
+    standby,
+    update::Updater,
 };
 
 type ModelMutex<T> = Mutex<CriticalSectionRawMutex, T>;
@@ -14,13 +31,17 @@ pub struct Model {
     /// Configuration loaded on boot
     pub config: Config,
     /// Configurator for writing a new configuration to flash
-    pub configurator: ModelMutex<Configurator<'static>>,
+    pub configurator: ModelMutex<Configurator>,
+    /// Updater for staging a new firmware image in flash
+    pub updater: ModelMutex<Updater>,
     /// Queue for commands to be sent
     pub send_queue: WriterQueue,
     /// Current status of the device
     status: ModelMutex<Status>,
-    /// Copy of the regulator's active control scheme
+    /// Copy of the user's active control scheme, before vehicle signals are applied
     control: ModelMutex<Control>,
+    /// Vehicle signals modifying the control scheme
+    signals: BlockingMutex<CriticalSectionRawMutex, Cell<ActiveSignals>>,
     /// A proxy for the regulator to push and pull directives/information
     regulator_proxy: &'static RegulatorProxy,
 }
@@ -28,7 +49,8 @@ pub struct Model {
 impl Model {
     pub fn new(
         config: ValidatedConfig,
-        configurator: Configurator<'static>,
+        configurator: Configurator,
+        updater: Updater,
         initial_status: Status,
         regulator_proxy: &'static RegulatorProxy,
     ) -> Self {
@@ -40,16 +62,18 @@ impl Model {
         Self {
             config,
             configurator: Mutex::new(configurator),
+            updater: Mutex::new(updater),
             status: Mutex::new(initial_status),
             control: Mutex::new(control),
+            signals: BlockingMutex::new(Cell::new(ActiveSignals::default())),
             regulator_proxy,
             send_queue: WriterQueue::new(),
         }
     }
 
-    pub async fn get_mode(&self) -> Mode {
+    pub async fn get_status(&self) -> Status {
         let lock = self.status.lock().await;
-        lock.mode.clone()
+        lock.clone()
     }
 
     pub async fn set_mode(&self, mode: Mode, notify: bool) {
@@ -61,14 +85,49 @@ impl Model {
         }
     }
 
-    pub async fn get_error(&self) -> HeadlightError {
-        let lock = self.status.lock().await;
-        lock.error.clone()
+    pub async fn raise_faults<I>(&self, errors: I, notify: bool)
+    where
+        I: IntoIterator<Item = HeadlightError>,
+    {
+        let mut lock = self.status.lock().await;
+
+        let mut raised = false;
+
+        for error in errors {
+            if !matches!(error, HeadlightError::None) {
+                raised = true;
+                log::record(
+                    LogLevel::Error,
+                    LogModule::Fault,
+                    fault::RAISED,
+                    error.mask(),
+                    0,
+                );
+            }
+
+            lock.raise(error);
+        }
+
+        if raised {
+            lock.latest_fault_time = timestamp();
+        }
+
+        if notify {
+            self.send_queue.send(lock.clone().into()).await;
+        }
     }
 
-    pub async fn set_error(&self, error: HeadlightError, notify: bool) {
+    pub async fn clear_faults(&self, faults: Faults, notify: bool) {
         let mut lock = self.status.lock().await;
-        lock.error = error;
+        lock.clear(faults);
+
+        log::record(
+            LogLevel::Info,
+            LogModule::Fault,
+            fault::CLEARED,
+            faults.bits,
+            0,
+        );
 
         if notify {
             self.send_queue.send(lock.clone().into()).await;
@@ -82,46 +141,101 @@ impl Model {
 
     // wrap on top of regulator proxy
 
+    /// The control the regulator should follow, with vehicle signals applied.
+    fn regulated(&self, control: Control) -> Control {
+        self.signals.lock(|signals| {
+            signals.get().apply(
+                control,
+                &self.config.signals,
+                self.config.max_target_current,
+            )
+        })
+    }
+
+    /// Only the user's control is notified, vehicle signals are reported separately.
     pub async fn set_control(&self, control: Control, notify: bool) {
         let mut lock = self.control.lock().await;
         *lock = control;
-        self.regulator_proxy.set_control(lock.clone());
+        self.regulator_proxy
+            .set_control(self.regulated(lock.clone()));
 
         if notify {
             self.send_queue.send(lock.clone().into()).await;
         }
     }
 
+    pub async fn set_signals(&self, signals: ActiveSignals) {
+        let lock = self.control.lock().await;
+        self.signals.lock(|cell| cell.set(signals));
+        self.regulator_proxy
+            .set_control(self.regulated(lock.clone()));
+    }
+
     pub async fn get_monitor_immediately(&self) -> Option<Monitor> {
         self.regulator_proxy.get_monitor_immediately().await
     }
 
+    pub async fn get_derating_immediately(&self) -> Option<Derating> {
+        self.regulator_proxy.get_derating_immediately().await
+    }
+
     pub async fn shutdown_regulation(&self) {
         self.regulator_proxy.shutdown().await;
     }
 
+    /// Park the half-bridge and sleep until woken, which resets the headlight.
+    pub async fn standby(&self) -> ! {
+        if self.config.enabled {
+            self.shutdown_regulation().await;
+            // let the regulator's final (idle) status be observed first
+            Timer::after(Duration::from_millis(10)).await;
+        }
+
+        self.set_mode(Mode::Standby, true).await;
+
+        info!("Entering standby.");
+        log::record(
+            LogLevel::Info,
+            LogModule::Standby,
+            standby_codes::ENTERED,
+            0,
+            0,
+        );
+        // give the status and log entry time to be sent
+        Timer::after(Duration::from_millis(100)).await;
+
+        standby::enter()
+    }
+
     pub async fn observe_regulator(&self) -> ! {
         loop {
             let status = self.regulator_proxy.wait_for_new_status().await;
-            self.set_mode(status.mode, true).await;
-            self.set_error(status.error, true).await;
+
+            if let Some(error) = status.error {
+                self.set_mode(status.mode, false).await;
+                self.raise_faults([error.into()], true).await;
+            } else {
+                self.set_mode(status.mode, true).await;
+            }
+
+            // entering or leaving throttling changes the derating reason
+            if let Some(derating) = self.get_derating_immediately().await {
+                self.send_queue.send(derating.into()).await;
+            }
         }
     }
 }
 
 #[embassy_executor::task]
 pub async fn model_worker(model: &'static Model) -> ! {
-    // notify status on startup
-    model
-        .send_queue
-        .send(
-            Status {
-                mode: model.get_mode().await,
-                error: model.get_error().await,
-            }
-            .into(),
-        )
-        .await;
+    // this image made it to the executor, so the bootloader may keep it
+    if let Err(e) = model.updater.lock().await.mark_booted() {
+        error!("Failed to confirm firmware image with error: {}.", e);
+    }
+
+    // notify properties and status on startup
+    model.send_queue.send(PROPERTIES.into()).await;
+    model.send_queue.send(model.get_status().await.into()).await;
 
     model.observe_regulator().await
 }
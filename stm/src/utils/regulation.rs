@@ -2,24 +2,21 @@ use crate::{
     fmt::{error, info},
     FaultLEDPin, HBEnablePin, MeasureResources, PWMTimer,
 };
-use common::{
-    command::commands::*, properties::PROPERTIES, types::*, utils::thermistor::celsius_to_sample,
-};
-use core::cmp::min;
+use common::{command::commands::*, properties::PROPERTIES, types::*};
+use core::cmp::{max, min};
 use embassy_stm32::{
-    adc::{Adc, Vref},
+    adc::Vref,
     gpio::Output,
-    peripherals::ADC,
     timer::{complementary_pwm::ComplementaryPwm, Channel},
 };
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
 use embassy_time::{Duration, Ticker};
 use pid::PIDController;
 
-use super::adc::{mv_to_ma, sample_to_mv};
+use super::adc::{mv_to_ma, sample_to_mv, SharedAdc};
 
 pub struct RegulatorHardware<'a> {
-    pub adc: Adc<'a, ADC>,
+    pub adc: &'a SharedAdc,
     pub vref: Vref,
     pub measure: MeasureResources,
 
@@ -29,10 +26,18 @@ pub struct RegulatorHardware<'a> {
     pub fault: Output<'a, FaultLEDPin>,
 }
 
+#[derive(Clone, Copy)]
+pub struct RegulatorStatus {
+    pub mode: Mode,
+    /// The error that caused regulation to stop, if any
+    pub error: Option<RuntimeError>,
+}
+
 pub struct RegulatorProxy {
     control: Signal<CriticalSectionRawMutex, Control>,
     monitor: Signal<CriticalSectionRawMutex, Monitor>,
-    status: Signal<CriticalSectionRawMutex, Status>,
+    derating: Signal<CriticalSectionRawMutex, Derating>,
+    status: Signal<CriticalSectionRawMutex, RegulatorStatus>,
     shutdown_start: Signal<CriticalSectionRawMutex, ()>,
     shutdown_confirm: Signal<CriticalSectionRawMutex, ()>,
 }
@@ -42,6 +47,7 @@ impl RegulatorProxy {
         Self {
             control: Signal::new(),
             monitor: Signal::new(),
+            derating: Signal::new(),
             status: Signal::new(),
             shutdown_start: Signal::new(),
             shutdown_confirm: Signal::new(),
@@ -61,15 +67,26 @@ impl RegulatorProxy {
         }
     }
 
+    pub async fn get_derating_immediately(&self) -> Option<Derating> {
+        if self.derating.signaled() {
+            // wait will be instantaneous because nothing else can wait for this signal
+            Some(self.derating.wait().await)
+        } else {
+            None
+        }
+    }
+
     /// Instruct regulator to shutdown and wait for confirmation.
     ///
     /// Note: This function will only exit once the regulator is confirmed to have shutdown.
     pub async fn shutdown(&self) {
         self.shutdown_start.signal(());
         self.shutdown_confirm.wait().await;
+        // the regulator never restarts, so leave the confirmation for subsequent calls
+        self.shutdown_confirm.signal(());
     }
 
-    pub async fn wait_for_new_status(&self) -> Status {
+    pub async fn wait_for_new_status(&self) -> RegulatorStatus {
         self.status.wait().await
     }
 }
@@ -84,6 +101,9 @@ pub struct Regulator<'a> {
     max_duty: u16,
     throttle_start: u16,
     throttle_stop: u16,
+
+    /// Duty at which the load was observed to start conducting during the startup probe
+    conduction_duty: u16,
 }
 
 impl<'a> Regulator<'a> {
@@ -104,8 +124,13 @@ impl<'a> Regulator<'a> {
             max_target: config.max_target_current,
             max_current: config.abs_max_load_current,
             max_duty,
-            throttle_start: celsius_to_sample(config.throttle_start),
-            throttle_stop: celsius_to_sample(config.throttle_stop),
+            throttle_start: PROPERTIES
+                .thermistor
+                .celsius_to_sample(config.throttle_start),
+            throttle_stop: PROPERTIES
+                .thermistor
+                .celsius_to_sample(config.throttle_stop),
+            conduction_duty: 0,
         }
     }
 
@@ -120,14 +145,80 @@ impl<'a> Regulator<'a> {
     }
 
     pub async fn get_reading(&mut self) -> Option<(u16, u16)> {
-        let vref_sample = self.hw.adc.read(&mut self.hw.vref).await;
-        let raw_temp = self.hw.adc.read(&mut self.hw.measure.temp).await;
-        let raw_current = self.hw.adc.read(&mut self.hw.measure.cur_sense).await; // measure current last to be more fresh ;)
+        let mut adc = self.hw.adc.lock().await;
+
+        let vref_sample = adc.read(&mut self.hw.vref).await;
+        let raw_temp = adc.read(&mut self.hw.measure.temp).await;
+        let raw_current = adc.read(&mut self.hw.measure.cur_sense).await; // measure current last to be more fresh ;)
 
         // raw temp is more accurate than comparing to vref since the measurement is a voltage divider from VDD
         Some((mv_to_ma(sample_to_mv(raw_current, vref_sample)?)?, raw_temp))
     }
 
+    /// Ramps the duty up from zero until the load draws a fraction of `target`.
+    ///
+    /// The ramp stops on current rather than duty, so it may go all the way to max duty.
+    /// Once the load conducts, the slope of current with duty (dI/dDuty) tells failures apart:
+    /// - a load that does not conduct even at max duty is open
+    /// - a slope too shallow to ever reach `target` means the supply voltage is too low
+    /// - a load that exceeds the maximum current as soon as it conducts at all is shorted
+    async fn probe(&mut self, ticker: &mut Ticker, target: u16) -> Result<(), RuntimeError> {
+        let step = max(1, self.max_duty / 64);
+        let probe_current = min(target, max(target / 4, PROPERTIES.max_adc_error * 4));
+        let mut duty = 0;
+        // duty and current when the load started conducting
+        let mut conduction = None;
+
+        let result = loop {
+            if duty >= self.max_duty {
+                break Err(match conduction {
+                    None => RuntimeError::OpenLoad,
+                    Some(_) => RuntimeError::InvariantLoad,
+                });
+            }
+
+            duty = min(self.max_duty, duty + step);
+            self.hw.pwm.set_duty(Self::CHANNEL, duty);
+            ticker.next().await;
+
+            let Some((current, _)) = self.get_reading().await else {
+                break Err(RuntimeError::ArithmeticError);
+            };
+
+            if current > self.max_current {
+                break Err(RuntimeError::ShortCircuit);
+            } else if current <= PROPERTIES.max_adc_error {
+                continue;
+            }
+
+            let (start_duty, start_current) = *conduction.get_or_insert((duty, current));
+
+            if current < probe_current {
+                continue;
+            }
+
+            // follow the measured slope to max duty, a load that jumped straight past
+            // the probe current has a slope too steep to measure
+            if duty > start_duty {
+                let projected = u32::from(current)
+                    + u32::from(current.saturating_sub(start_current))
+                        * u32::from(self.max_duty - duty)
+                        / u32::from(duty - start_duty);
+
+                if projected < u32::from(target) {
+                    break Err(RuntimeError::InvariantLoad);
+                }
+            }
+
+            self.conduction_duty = start_duty;
+            break Ok(());
+        };
+
+        self.hw.pwm.set_duty(Self::CHANNEL, 0);
+
+        result
+    }
+
     fn check_fault(
         &self,
         current: u16,
@@ -138,8 +229,15 @@ impl<'a> Regulator<'a> {
         if current > self.max_current + PROPERTIES.max_adc_error {
             // current is sufficiently over max target to be considered unsafe
             Some(RuntimeError::Overcurrent)
+        } else if duty <= self.conduction_duty / 2 && current > self.max_target {
+            // well below the duty the load started conducting at,
+            // current should be negligible (dI/dDuty is implausibly high)
+            Some(RuntimeError::ShortCircuit)
+        } else if duty == self.max_duty && current <= PROPERTIES.max_adc_error {
+            // nothing flows even at max duty (the load or its connector is broken)
+            Some(RuntimeError::OpenLoad)
         } else if current < target && duty == self.max_duty {
-            // current is low at max duty (load is disconnected or supply voltage is too low)
+            // the load conducts but falls short at max duty (supply voltage is too low)
             Some(RuntimeError::InvariantLoad)
         } else if temperature > self.throttle_stop {
             Some(RuntimeError::Overtemperature)
@@ -148,10 +246,11 @@ impl<'a> Regulator<'a> {
         }
     }
 
-    fn thermal_throttle(&self, temperature: u16, target: u16) -> Result<(u16, bool), RuntimeError> {
+    fn thermal_throttle(&self, temperature: u16, target: u16) -> Result<Derating, RuntimeError> {
         if temperature >= self.throttle_start {
-            Ok((
-                min(
+            Ok(Derating {
+                requested_target: target,
+                effective_target: min(
                     target,
                     // linear ramp
                     u32::from(self.throttle_stop - temperature)
@@ -162,10 +261,14 @@ impl<'a> Regulator<'a> {
                         .try_into()
                         .map_err(|_| RuntimeError::ArithmeticError)?,
                 ),
-                true,
-            ))
+                reason: DeratingReason::Temperature,
+            })
         } else {
-            Ok((target, false))
+            Ok(Derating {
+                requested_target: target,
+                effective_target: target,
+                reason: DeratingReason::None,
+            })
         }
     }
 
@@ -202,17 +305,27 @@ impl<'a> Regulator<'a> {
         let mut upper_current = 0;
         let mut lower_current = 0;
 
-        let mut status = Status {
+        let mut status = RegulatorStatus {
             mode: Mode::Running,
-            error: HeadlightError::None,
+            error: None,
         };
 
         self.startup();
         proxy.status.signal(status);
 
         let mut ticker = Ticker::every(Duration::from_ticks(4));
+        let mut probed = false;
 
         let error = loop {
+            // check the load is plausible before regulating, once there is a target to drive it to
+            if !probed && control.target > 0 {
+                if let Err(e) = self.probe(&mut ticker, control.target).await {
+                    break Some(e);
+                }
+
+                probed = true;
+            }
+
             if let Some((current, temperature)) = self.get_reading().await {
                 if let Some(error) = self.check_fault(current, control.target, temperature, duty) {
                     break Some(error);
@@ -220,7 +333,12 @@ impl<'a> Regulator<'a> {
 
                 // get throttled target
                 let target = match self.thermal_throttle(temperature, control.target) {
-                    Ok((throttled_target, throttling)) => {
+                    Ok(derating) => {
+                        // push derating before status so it is available when the mode changes
+                        proxy.derating.signal(derating);
+
+                        let throttling = derating.reason != DeratingReason::None;
+
                         if throttling && status.mode != Mode::Throttling {
                             status.mode = Mode::Throttling;
                             proxy.status.signal(status);
@@ -229,7 +347,7 @@ impl<'a> Regulator<'a> {
                             proxy.status.signal(status);
                         }
 
-                        throttled_target
+                        derating.effective_target
                     }
                     Err(e) => {
                         break Some(e);
@@ -279,12 +397,21 @@ impl<'a> Regulator<'a> {
             }
         };
 
+        self.finish(proxy, status, error);
+    }
+
+    fn finish(
+        &mut self,
+        proxy: &RegulatorProxy,
+        mut status: RegulatorStatus,
+        error: Option<RuntimeError>,
+    ) {
         self.shutdown();
         proxy.shutdown_confirm.signal(());
 
         if let Some(error) = error {
             status.mode = Mode::Fault;
-            status.error = error.into();
+            status.error = Some(error);
             self.hw.fault.set_high();
             error!(
                 "The current state was determined to be unsafe for reason: {}. Shutting down.",
@@ -0,0 +1,155 @@
+use common::{
+    command::commands::{Control, SignalConfig, SignalEvent},
+    types::{LogLevel, LogModule, Side, VehicleSignal},
+    utils::log::codes::signal as signal_codes,
+};
+use embassy_futures::select::select4;
+use embassy_stm32::{
+    exti::ExtiInput,
+    gpio::{Input, Pull},
+};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+
+use crate::{
+    fmt::info,
+    utils::{log, model::Model},
+    BrakePin, IgnitionPin, SignalResources, TurnLeftPin, TurnRightPin,
+};
+
+/// Signals are considered settled once they have held a level for this long.
+const DEBOUNCE: Duration = Duration::from_millis(20);
+/// Turn signals blink, so they are held active for this long after each blink.
+const TURN_HOLD: Duration = Duration::from_millis(800);
+
+pub struct SignalInputs {
+    turn_left: ExtiInput<'static, TurnLeftPin>,
+    turn_right: ExtiInput<'static, TurnRightPin>,
+    brake: ExtiInput<'static, BrakePin>,
+    ignition: ExtiInput<'static, IgnitionPin>,
+}
+
+/// The vehicle's signals are level shifted to be active high, unconnected signals read inactive.
+pub fn setup_signals(r: SignalResources) -> SignalInputs {
+    SignalInputs {
+        turn_left: ExtiInput::new(Input::new(r.turn_left, Pull::Down), r.turn_left_exti),
+        turn_right: ExtiInput::new(Input::new(r.turn_right, Pull::Down), r.turn_right_exti),
+        brake: ExtiInput::new(Input::new(r.brake, Pull::Down), r.brake_exti),
+        ignition: ExtiInput::new(Input::new(r.ignition, Pull::Down), r.ignition_exti),
+    }
+}
+
+/// The vehicle's signals currently in effect.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct ActiveSignals {
+    pub turn_left: bool,
+    pub turn_right: bool,
+    pub brake: bool,
+    pub ignition: bool,
+}
+
+impl ActiveSignals {
+    fn iter(&self) -> impl Iterator<Item = (VehicleSignal, bool)> {
+        [
+            (VehicleSignal::TurnLeft, self.turn_left),
+            (VehicleSignal::TurnRight, self.turn_right),
+            (VehicleSignal::Brake, self.brake),
+            (VehicleSignal::Ignition, self.ignition),
+        ]
+        .into_iter()
+    }
+
+    /// The control to regulate to, given the user's `control`.
+    pub fn apply(&self, control: Control, config: &SignalConfig, max_target: u16) -> Control {
+        let turning = match config.side {
+            Side::Left => self.turn_left,
+            Side::Right => self.turn_right,
+        };
+
+        let mut target = control.target as u32;
+
+        if turning {
+            target = target * config.turn_dim as u32 / 100;
+        }
+
+        if self.brake {
+            target += config.brake_boost as u32;
+        }
+
+        Control {
+            target: target.min(max_target as u32) as u16,
+        }
+    }
+}
+
+impl SignalInputs {
+    async fn any_edge(&mut self) {
+        select4(
+            self.turn_left.wait_for_any_edge(),
+            self.turn_right.wait_for_any_edge(),
+            self.brake.wait_for_any_edge(),
+            self.ignition.wait_for_any_edge(),
+        )
+        .await;
+    }
+}
+
+#[embassy_executor::task]
+pub async fn signal_worker(mut inputs: SignalInputs, model: &'static Model) -> ! {
+    let mut active = ActiveSignals::default();
+    let mut last_blink = [None::<Instant>; 2];
+
+    loop {
+        let now = Instant::now();
+
+        for (last, high) in last_blink
+            .iter_mut()
+            .zip([inputs.turn_left.is_high(), inputs.turn_right.is_high()])
+        {
+            if high {
+                *last = Some(now);
+            }
+        }
+
+        let held = |last: Option<Instant>| last.is_some_and(|last| now - last < TURN_HOLD);
+
+        let new = ActiveSignals {
+            turn_left: held(last_blink[0]),
+            turn_right: held(last_blink[1]),
+            brake: inputs.brake.is_high(),
+            ignition: inputs.ignition.is_high(),
+        };
+
+        if new != active {
+            for ((signal, was), (_, is)) in active.iter().zip(new.iter()) {
+                if was != is {
+                    info!("Vehicle signal {} is now {}.", signal, is);
+                    log::record(
+                        LogLevel::Info,
+                        LogModule::Signal,
+                        signal_codes::CHANGED,
+                        signal as u32,
+                        is as u32,
+                    );
+
+                    model
+                        .send_queue
+                        .send(SignalEvent { signal, active: is }.into())
+                        .await;
+                }
+            }
+
+            let ignition_off = active.ignition && !new.ignition;
+
+            active = new;
+            model.set_signals(active).await;
+
+            if ignition_off && model.config.signals.ignition_standby {
+                model.standby().await;
+            }
+        }
+
+        // a held turn signal lapses without an edge
+        with_timeout(TURN_HOLD, inputs.any_edge()).await.ok();
+        Timer::after(DEBOUNCE).await;
+    }
+}
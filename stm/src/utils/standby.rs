@@ -0,0 +1,134 @@
+use core::ptr::{addr_of, addr_of_mut};
+
+use cortex_m::peripheral::SCB;
+use embassy_stm32::pac::{
+    self,
+    iwdg::vals::{Key, Pr},
+    pwr::vals::Pdds,
+};
+
+extern "C" {
+    /// Kept across resets, see the `STANDBY` region in memory.x.
+    static mut __standby: u32;
+}
+
+/// Marks that the headlight is in standby, anything else (like garbage after power-up) does not.
+const STANDBY_MARKER: u32 = 0x57a1_db10;
+
+const PORT_A: u8 = 0;
+const PORT_B: u8 = 1;
+
+struct WakeSource {
+    line: usize,
+    port: u8,
+    rising: bool,
+    falling: bool,
+}
+
+/// Must match the pins in `assign_resources!`.
+const WAKE_SOURCES: [WakeSource; 4] = [
+    // start bit of the relay's wake byte (anything received while asleep is lost)
+    WakeSource {
+        line: 10,
+        port: PORT_A,
+        rising: false,
+        falling: true,
+    },
+    // button press
+    WakeSource {
+        line: 0,
+        port: PORT_A,
+        rising: false,
+        falling: true,
+    },
+    // high/low switch moved
+    WakeSource {
+        line: 1,
+        port: PORT_A,
+        rising: true,
+        falling: true,
+    },
+    // ignition turned on
+    WakeSource {
+        line: 4,
+        port: PORT_B,
+        rising: true,
+        falling: false,
+    },
+];
+
+fn mark(standby: bool) {
+    let marker = if standby { STANDBY_MARKER } else { 0 };
+
+    unsafe { addr_of_mut!(__standby).write_volatile(marker) }
+}
+
+/// Whether the headlight was reset by the watchdog while in standby, and must go straight back.
+///
+/// Clears the reset flags, so this must only be called once per boot.
+pub fn resuming() -> bool {
+    let marked = unsafe { addr_of!(__standby).read_volatile() } == STANDBY_MARKER;
+    let watchdog = pac::RCC.csr().read().iwdgrstf();
+
+    pac::RCC.csr().modify(|w| w.set_rmvf(true));
+
+    if !(marked && watchdog) {
+        mark(false);
+        return false;
+    }
+
+    true
+}
+
+/// The watchdog cannot be stopped, so it is stretched to its longest period (about 26 s).
+fn stretch_watchdog() {
+    pac::IWDG.kr().write(|w| w.set_key(Key::ENABLE));
+    pac::IWDG.pr().write(|w| w.set_pr(Pr::DIVIDEBY256));
+    pac::IWDG.rlr().write(|w| w.set_rl(0xfff));
+    pac::IWDG.kr().write(|w| w.set_key(Key::RESET));
+}
+
+fn arm_wake_sources() {
+    for source in WAKE_SOURCES {
+        pac::SYSCFG
+            .exticr(source.line / 4)
+            .modify(|w| w.set_exti(source.line % 4, source.port));
+        pac::EXTI
+            .rtsr(0)
+            .modify(|w| w.set_line(source.line, source.rising));
+        pac::EXTI
+            .ftsr(0)
+            .modify(|w| w.set_line(source.line, source.falling));
+        pac::EXTI.pr(0).write(|w| w.set_line(source.line, true));
+        pac::EXTI.imr(0).modify(|w| w.set_line(source.line, true));
+    }
+}
+
+/// Stop every clock until a wake source fires, then reset into a normal boot.
+///
+/// The half-bridge must already be parked.
+/// Each time the watchdog expires the headlight briefly boots and comes straight back here.
+pub fn enter() -> ! {
+    mark(true);
+    cortex_m::interrupt::disable();
+
+    stretch_watchdog();
+    arm_wake_sources();
+
+    pac::RCC.apb1enr().modify(|w| w.set_pwren(true));
+    pac::PWR.cr().modify(|w| {
+        w.set_pdds(Pdds::STOP_MODE);
+        w.set_lpds(true);
+    });
+
+    // a pending interrupt ends the wait even while interrupts are disabled
+    unsafe {
+        let mut scb = cortex_m::Peripherals::steal().SCB;
+        scb.set_sleepdeep();
+    }
+    cortex_m::asm::dsb();
+    cortex_m::asm::wfi();
+
+    mark(false);
+    SCB::sys_reset()
+}
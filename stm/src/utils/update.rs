@@ -0,0 +1,130 @@
+use common::{command::commands::*, types::FirmwareError, IMAGE_CRC};
+use core::cmp::min;
+use crc::Digest;
+use embassy_boot_stm32::{
+    AlignedBuffer, BlockingFirmwareUpdater, FirmwareUpdaterConfig, FirmwareUpdaterError,
+};
+use embassy_embedded_hal::flash::partition::BlockingPartition;
+use embassy_stm32::flash::{Blocking, Flash, WRITE_SIZE};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use static_cell::StaticCell;
+
+use super::flash::SharedFlash;
+
+type Partition = BlockingPartition<'static, NoopRawMutex, Flash<'static, Blocking>>;
+
+static MAGIC: StaticCell<AlignedBuffer<WRITE_SIZE>> = StaticCell::new();
+
+fn into_firmware_error(e: FirmwareUpdaterError) -> FirmwareError {
+    match e {
+        FirmwareUpdaterError::BadState => FirmwareError::BadState,
+        _ => FirmwareError::Flash,
+    }
+}
+
+struct Session {
+    size: u32,
+    crc: u32,
+    received: u32,
+    digest: Digest<'static, u32>,
+}
+
+/// Stages a new image in the DFU partition for the bootloader to swap in.
+pub struct Updater {
+    inner: BlockingFirmwareUpdater<'static, Partition, Partition>,
+    /// Separate handle to the DFU partition for streaming chunks into
+    dfu: Partition,
+    session: Option<Session>,
+}
+
+impl Updater {
+    pub fn new(flash: &'static SharedFlash) -> Self {
+        let magic = MAGIC.init(AlignedBuffer([0; WRITE_SIZE]));
+
+        Self {
+            inner: BlockingFirmwareUpdater::new(
+                FirmwareUpdaterConfig::from_linkerfile_blocking(flash),
+                &mut magic.0,
+            ),
+            dfu: FirmwareUpdaterConfig::from_linkerfile_blocking(flash).dfu,
+            session: None,
+        }
+    }
+
+    /// Confirms the running image.
+    ///
+    /// If this is not called after an update, the bootloader reverts to the previous image on the next reset.
+    pub fn mark_booted(&mut self) -> Result<(), FirmwareError> {
+        self.inner.mark_booted().map_err(into_firmware_error)
+    }
+
+    pub fn begin(&mut self, begin: FirmwareBegin) -> Result<(), FirmwareError> {
+        // the DFU partition is one page larger than the active partition for the swap
+        let capacity = self.dfu.capacity() - Partition::ERASE_SIZE;
+
+        if begin.size as usize > capacity {
+            return Err(FirmwareError::TooLarge);
+        }
+
+        self.session = None;
+        self.inner.prepare_update().map_err(into_firmware_error)?;
+        self.session = Some(Session {
+            size: begin.size,
+            crc: begin.crc,
+            received: 0,
+            digest: IMAGE_CRC.digest(),
+        });
+
+        Ok(())
+    }
+
+    pub fn write(&mut self, chunk: FirmwareChunk) -> Result<(), FirmwareError> {
+        let session = self.session.as_mut().ok_or(FirmwareError::NotStarted)?;
+
+        if chunk.offset != session.received {
+            return Err(FirmwareError::OutOfOrder);
+        }
+
+        if session.received >= session.size {
+            return Err(FirmwareError::TooLarge);
+        }
+
+        // padding of the final chunk is written but excluded from the CRC
+        let len = min(FIRMWARE_CHUNK_SIZE as u32, session.size - session.received);
+
+        self.dfu
+            .write(chunk.offset, &chunk.data)
+            .map_err(|_| FirmwareError::Flash)?;
+        session.digest.update(&chunk.data[..len as usize]);
+        session.received += len;
+
+        Ok(())
+    }
+
+    /// Verifies the staged image and marks it for the bootloader to swap in on the next reset.
+    pub fn apply(&mut self) -> Result<(), FirmwareError> {
+        let session = self.session.take().ok_or(FirmwareError::NotStarted)?;
+
+        if session.received != session.size {
+            return Err(FirmwareError::Incomplete);
+        }
+
+        if session.digest.finalize() != session.crc {
+            return Err(FirmwareError::Crc);
+        }
+
+        self.inner.mark_updated().map_err(into_firmware_error)
+    }
+
+    pub fn abort(&mut self) {
+        self.session = None;
+    }
+
+    pub fn status(&self, result: Result<(), FirmwareError>) -> FirmwareStatus {
+        FirmwareStatus {
+            received: self.session.as_ref().map_or(0, |session| session.received),
+            error: result.err().unwrap_or_default(),
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use core::ptr::{addr_of, addr_of_mut, copy_nonoverlapping};
+
+use embassy_stm32::pac::{self, syscfg::vals::MemMode};
+
+/// 16 core exceptions and 32 interrupts, see the `VECTORS` region in memory.x.
+const VECTOR_WORDS: usize = 48;
+
+extern "C" {
+    /// The application's vector table in flash, placed by cortex-m-rt.
+    static __vector_table: [u32; VECTOR_WORDS];
+    /// Reserved at the start of RAM by both the application and the bootloader.
+    static mut __vectors: [u32; VECTOR_WORDS];
+}
+
+/// The Cortex-M0 has no VTOR, so after the bootloader jumps here, interrupts still go through
+/// the bootloader's vector table (whose handlers reset). Copying the table to the start of SRAM
+/// and mapping SRAM at address 0 puts the application's handlers in its place.
+///
+/// Must be called before any interrupt is enabled.
+pub fn relocate_vector_table() {
+    unsafe {
+        copy_nonoverlapping(
+            addr_of!(__vector_table).cast::<u32>(),
+            addr_of_mut!(__vectors).cast::<u32>(),
+            VECTOR_WORDS,
+        );
+    }
+
+    pac::RCC.apb2enr().modify(|w| w.set_syscfgen(true));
+    pac::SYSCFG
+        .cfgr1()
+        .modify(|w| w.set_mem_mode(MemMode::SRAM));
+
+    cortex_m::asm::dsb();
+    cortex_m::asm::isb();
+}
@@ -0,0 +1,26 @@
+use embassy_stm32::{peripherals::IWDG, wdg::IndependentWatchdog};
+use embassy_time::{Duration, Ticker};
+
+/// Long enough to cover erasing the whole DFU partition.
+const TIMEOUT_US: u32 = 2_000_000;
+const PET_PERIOD: Duration = Duration::from_millis(250);
+
+pub fn setup_watchdog<'a>(iwdg: IWDG) -> IndependentWatchdog<'a, IWDG> {
+    let mut watchdog = IndependentWatchdog::new(iwdg, TIMEOUT_US);
+    watchdog.unleash();
+
+    watchdog
+}
+
+/// Keeps the watchdog at bay while the normal executor is alive.
+///
+/// A hung or panicked image is reset, which lets the bootloader revert an unconfirmed update.
+#[embassy_executor::task]
+pub async fn watchdog_worker(mut watchdog: IndependentWatchdog<'static, IWDG>) -> ! {
+    let mut ticker = Ticker::every(PET_PERIOD);
+
+    loop {
+        watchdog.pet();
+        ticker.next().await;
+    }
+}